@@ -57,6 +57,16 @@ pub fn benchmark_wals(c: &mut Criterion) {
     });
     append_100k.finish();
 
+    let mut append_batch_10k = c.benchmark_group("append_batch_10k");
+    append_batch_10k.significance_level(0.3).sample_size(10);
+    append_batch_10k.bench_function("append_batch_10k", |b| {
+        b.iter(|| {
+            let batch = vec![Vec::from([1; 32]); 10_000];
+            let _ = topic.append_batch(&batch);
+        })
+    });
+    append_batch_10k.finish();
+
     // c.bench_function("append_500k", |b| {
     //     b.iter(|| {
     //         for _ in 0..500_000 {
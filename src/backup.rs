@@ -0,0 +1,188 @@
+use std::path::Path;
+
+use rocksdb::backup::{BackupEngine, BackupEngineInfo, BackupEngineOptions, RestoreOptions};
+
+use crate::{
+    builder::StructDB,
+    database::Database,
+    errors::{Error, Result},
+};
+
+/// Backup of a database stored by `Backups`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackupInfo {
+    pub id: u32,
+    /// Creation time in epoch seconds.
+    pub timestamp: i64,
+    /// Size in bytes, including files shared with other backups.
+    pub size: u64,
+    pub num_files: u32,
+}
+
+impl From<BackupEngineInfo> for BackupInfo {
+    fn from(info: BackupEngineInfo) -> Self {
+        Self {
+            id: info.backup_id,
+            timestamp: info.timestamp,
+            size: info.size,
+            num_files: info.num_files,
+        }
+    }
+}
+
+/// Incremental backups of a database in a backup directory.
+///
+/// Every backup contains all column families, files which did not change since
+/// an earlier backup are shared with it. Backups are taken from a live database
+/// while it is written to.
+pub struct Backups {
+    engine: BackupEngine,
+}
+
+impl Backups {
+    /// Opens the backup directory, it is created if missing.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let options = BackupEngineOptions::new(dir)?;
+        let env = rocksdb::Env::new()?;
+        let engine = BackupEngine::open(&options, &env)?;
+        Ok(Self { engine })
+    }
+
+    /// Flushes the memtables and backs up the database.
+    pub fn create(&mut self, db: &StructDB) -> Result<BackupInfo> {
+        self.engine
+            .create_new_backup_flush(db.raw().as_ref(), true)?;
+
+        // NOTE: Backup ids always increase, the new backup is the last one.
+        self.list().pop().ok_or(Error::BackupNotFound)
+    }
+
+    /// All backups ordered by id.
+    pub fn list(&self) -> Vec<BackupInfo> {
+        let mut backups: Vec<BackupInfo> = self
+            .engine
+            .get_backup_info()
+            .into_iter()
+            .map(BackupInfo::from)
+            .collect();
+        backups.sort_by_key(|backup| backup.id);
+        backups
+    }
+
+    /// Checks that all files of the backup exist with the expected size.
+    pub fn verify(&self, id: u32) -> Result<()> {
+        self.engine.verify_backup(id).map_err(Into::into)
+    }
+
+    /// Deletes all but the newest `keep` backups.
+    pub fn purge(&mut self, keep: usize) -> Result<()> {
+        self.engine.purge_old_backups(keep).map_err(Into::into)
+    }
+
+    /// Restores the backup into `db_path`, the database must not be open.
+    ///
+    /// Fails with `Error::DatabaseOpen` if this process has the database open.
+    pub fn restore<P: AsRef<Path>>(&mut self, id: u32, db_path: P) -> Result<()> {
+        let db_path = ensure_closed(db_path.as_ref())?;
+        self.engine
+            .restore_from_backup(db_path, db_path, &RestoreOptions::default(), id)
+            .map_err(Into::into)
+    }
+
+    /// Restores the newest backup into `db_path`, see `restore`.
+    pub fn restore_latest<P: AsRef<Path>>(&mut self, db_path: P) -> Result<()> {
+        let db_path = ensure_closed(db_path.as_ref())?;
+        self.engine
+            .restore_from_latest_backup(db_path, db_path, &RestoreOptions::default())
+            .map_err(Into::into)
+    }
+}
+
+/// Restoring overwrites the files of a live database, which corrupts it.
+fn ensure_closed(db_path: &Path) -> Result<&Path> {
+    if Database::is_open(db_path) {
+        return Err(Error::DatabaseOpen(db_path.display().to_string()));
+    }
+    Ok(db_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{builder::StructDB, caches::Caches, errors::Error, table::Table, topic::Topic};
+
+    use super::Backups;
+
+    struct Settings;
+
+    impl Table for Settings {
+        const NAME: &'static str = "settings";
+    }
+
+    struct Events;
+
+    impl Table for Events {
+        const NAME: &'static str = "events";
+    }
+
+    impl Topic for Events {}
+
+    #[test]
+    fn test_backup_restore() {
+        let _ = fs::remove_dir_all("test_backup_restore.db");
+        let _ = fs::remove_dir_all("test_backup_restore.backup");
+        let _ = fs::remove_dir_all("test_backup_restore.restored");
+        let db = StructDB::builder("test_backup_restore.db", Caches::default())
+            .with_struct::<Settings>()
+            .with_struct::<Events>()
+            .build()
+            .unwrap();
+
+        let settings = db.make_table::<Settings>();
+        let shard = db.make_sharded_table::<Settings>(&"eu".to_string());
+        let mut events = db.make_topic::<Events>();
+
+        settings.insert("mode", "a").unwrap();
+        events.append(&b"first".to_vec()).unwrap();
+        let first = db.backup_to("test_backup_restore.backup").unwrap();
+
+        settings.insert("mode", "b").unwrap();
+        shard.insert("mode", "eu").unwrap();
+        events.append(&b"second".to_vec()).unwrap();
+        events
+            .window("reader", 10)
+            .next_timeout(Default::default())
+            .unwrap();
+        let second = db.backup_to("test_backup_restore.backup").unwrap();
+        assert!(second.id > first.id);
+
+        let mut backups = Backups::open("test_backup_restore.backup").unwrap();
+        assert_eq!(backups.list(), vec![first, second]);
+        backups.verify(first.id).unwrap();
+        backups.purge(1).unwrap();
+        assert_eq!(backups.list(), vec![second]);
+
+        let result = backups.restore(second.id, "test_backup_restore.db");
+        assert!(matches!(result, Err(Error::DatabaseOpen(_))));
+
+        StructDB::restore_from(
+            "test_backup_restore.backup",
+            second.id,
+            "test_backup_restore.restored",
+        )
+        .unwrap();
+        let restored = StructDB::builder("test_backup_restore.restored", Caches::default())
+            .build_all()
+            .unwrap();
+
+        let settings = restored.make_table::<Settings>();
+        assert_eq!(settings.get("mode").unwrap().unwrap().as_ref(), b"b");
+        let shard = restored.make_sharded_table::<Settings>(&"eu".to_string());
+        assert_eq!(shard.get("mode").unwrap().unwrap().as_ref(), b"eu");
+
+        let events = restored.make_topic::<Events>();
+        assert_eq!(events.iter().count(), 2);
+        assert_eq!(events.consumers().list().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,192 @@
+use rocksdb::WriteBatch;
+
+use crate::{
+    errors::{Error, Result},
+    table::{Table, TableImpl},
+};
+
+/// Write of a key staged in a `Batch`, `value` is `None` for a delete.
+pub(crate) struct StagedWrite {
+    pub cf_name: String,
+    pub key: Vec<u8>,
+    pub value: Option<Vec<u8>>,
+}
+
+/// Key which must not be owned by another row when the batch is written.
+struct UniqueCheck {
+    cf_name: String,
+    key: Vec<u8>,
+    owner: Vec<u8>,
+}
+
+/// Stored value of a key which the staged writes were derived from.
+struct RowCheck {
+    cf_name: String,
+    key: Vec<u8>,
+    value: Option<Vec<u8>>,
+}
+
+/// Write batch which keeps track of the keys it writes.
+///
+/// A batch written with `TableImpl::apply` holds the key locks of all staged
+/// keys while it is written, so it is atomic with respect to `compare_and_swap`
+/// and `update`. Writes of several tables of the same database can be staged.
+#[derive(Default)]
+pub struct Batch {
+    inner: WriteBatch,
+    writes: Vec<StagedWrite>,
+    checks: Vec<UniqueCheck>,
+    rows: Vec<RowCheck>,
+}
+
+impl Batch {
+    pub fn put<T, K, V>(&mut self, table: &TableImpl<T>, key: K, value: V)
+    where
+        T: Table,
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        self.inner.put_cf(&table.cf(), key.as_ref(), value.as_ref());
+        self.writes.push(StagedWrite {
+            cf_name: table.name.clone(),
+            key: key.as_ref().to_vec(),
+            value: Some(value.as_ref().to_vec()),
+        });
+    }
+
+    pub fn delete<T, K>(&mut self, table: &TableImpl<T>, key: K)
+    where
+        T: Table,
+        K: AsRef<[u8]>,
+    {
+        self.inner.delete_cf(&table.cf(), key.as_ref());
+        self.writes.push(StagedWrite {
+            cf_name: table.name.clone(),
+            key: key.as_ref().to_vec(),
+            value: None,
+        });
+    }
+
+    /// Last write staged for the key, `Some(None)` if the batch deletes it.
+    pub fn staged<T: Table>(&self, table: &TableImpl<T>, key: &[u8]) -> Option<Option<&[u8]>> {
+        self.writes
+            .iter()
+            .rev()
+            .find(|write| write.cf_name == table.name && write.key == key)
+            .map(|write| write.value.as_deref())
+    }
+
+    /// Requires that `key` is free or owned by `owner` once the batch is written.
+    ///
+    /// Writes staged earlier in the batch are checked right away, the stored
+    /// value is checked by `verify` while the key is locked.
+    pub(crate) fn require_unique<T: Table>(
+        &mut self,
+        table: &TableImpl<T>,
+        key: &[u8],
+        owner: &[u8],
+    ) -> Result<()> {
+        match self.staged(table, key) {
+            Some(Some(staged)) if staged != owner => {
+                Err(Error::UniqueIndexViolation(table.name.clone()))
+            }
+            Some(_) => Ok(()),
+            None => {
+                self.checks.push(UniqueCheck {
+                    cf_name: table.name.clone(),
+                    key: key.to_vec(),
+                    owner: owner.to_vec(),
+                });
+                Ok(())
+            }
+        }
+    }
+
+    /// Requires that `key` still stores `value` once the batch is written.
+    ///
+    /// Index entries are staged from the stored row, a concurrent write of the
+    /// row would leave them stale. The value is checked by `verify` while the
+    /// key is locked.
+    pub(crate) fn require_stored<T: Table>(
+        &mut self,
+        table: &TableImpl<T>,
+        key: &[u8],
+        value: Option<&[u8]>,
+    ) {
+        self.rows.push(RowCheck {
+            cf_name: table.name.clone(),
+            key: key.to_vec(),
+            value: value.map(|value| value.to_vec()),
+        });
+    }
+
+    /// Checks the stored owners of unique keys and the stored rows, called while
+    /// all keys are locked.
+    ///
+    /// Keys which the batch deletes are skipped, their stored owner gives them up.
+    /// A changed row fails with `Error::TransactionConflict`, staging the batch
+    /// again from the new row resolves it.
+    pub(crate) fn verify(&self, db: &rocksdb::DB) -> Result<()> {
+        for row in &self.rows {
+            let cf = db
+                .cf_handle(&row.cf_name)
+                .ok_or_else(|| Error::ColumnFamilyNotFound(row.cf_name.clone()))?;
+            if db.get_pinned_cf(&cf, &row.key)?.as_deref() != row.value.as_deref() {
+                return Err(Error::TransactionConflict(row.cf_name.clone()));
+            }
+        }
+
+        for check in &self.checks {
+            let released = self.writes.iter().any(|write| {
+                write.value.is_none() && write.cf_name == check.cf_name && write.key == check.key
+            });
+            if released {
+                continue;
+            }
+
+            let cf = db
+                .cf_handle(&check.cf_name)
+                .ok_or_else(|| Error::ColumnFamilyNotFound(check.cf_name.clone()))?;
+            match db.get_pinned_cf(&cf, &check.key)? {
+                Some(owner) if owner.as_ref() != check.owner.as_slice() => {
+                    return Err(Error::UniqueIndexViolation(check.cf_name.clone()));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of staged writes.
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Column family names and keys which are locked while the batch is written.
+    pub(crate) fn keys(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        let checks = self
+            .checks
+            .iter()
+            .map(|check| (check.cf_name.as_str(), check.key.as_slice()));
+        let rows = self
+            .rows
+            .iter()
+            .map(|row| (row.cf_name.as_str(), row.key.as_slice()));
+        self.written().chain(checks).chain(rows)
+    }
+
+    /// Column family names and keys of the staged writes.
+    pub(crate) fn written(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.writes
+            .iter()
+            .map(|write| (write.cf_name.as_str(), write.key.as_slice()))
+    }
+
+    pub(crate) fn into_parts(self) -> (WriteBatch, Vec<StagedWrite>) {
+        (self.inner, self.writes)
+    }
+}
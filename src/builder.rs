@@ -1,12 +1,27 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    any::Any,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
 
 use crate::{
+    backup::{BackupInfo, Backups},
     caches::Caches,
+    changelog::ChangelogTable,
     database::Database,
     errors::Error,
+    follow::Notifier,
+    handle::Migrations,
+    locks::KeyLocks,
+    shared_topic::SharedTopic,
+    snapshot::DatabaseSnapshot,
     stats::Stats,
     table::{Table, TableImpl},
     topic::{Topic, TopicImpl},
+    transaction::{Transaction, TransactionMode},
+    typed_table::{TypedTable, TypedTableImpl},
+    watch::Watchers,
 };
 
 pub type Version = [u8; 3];
@@ -49,6 +64,7 @@ pub struct Builder {
     options: rocksdb::Options,
     caches: Caches,
     descriptors: Vec<rocksdb::ColumnFamilyDescriptor>,
+    transaction_mode: TransactionMode,
 }
 
 impl Builder {
@@ -58,6 +74,7 @@ impl Builder {
             options: Default::default(),
             caches,
             descriptors: Default::default(),
+            transaction_mode: Default::default(),
         }
     }
 
@@ -69,15 +86,24 @@ impl Builder {
         self
     }
 
+    /// Opens the database with all of its column families, ones not registered
+    /// with `with_struct` use default options.
     pub fn build_all(mut self) -> Result<StructDB, rocksdb::Error> {
         if self.path.exists() {
-            for cf in Database::list_cf(self.path.clone())? {
+            self.add_existing_cfs()?;
+        }
+
+        self.build()
+    }
+
+    fn add_existing_cfs(&mut self) -> Result<(), rocksdb::Error> {
+        for cf in Database::list_cf(self.path.clone())? {
+            if !self.descriptors.iter().any(|cfd| cfd.name() == cf) {
                 self.descriptors
                     .push(rocksdb::ColumnFamilyDescriptor::new(cf, Default::default()));
             }
         }
-
-        self.build()
+        Ok(())
     }
 
     pub fn with_struct<T>(mut self) -> Self
@@ -91,20 +117,64 @@ impl Builder {
         self
     }
 
-    pub fn build(self) -> Result<StructDB, rocksdb::Error> {
+    /// Opens the database and migrates it to the target version of `migrations`.
+    ///
+    /// A newly created database is stamped with the target version directly.
+    pub fn build_with_migrations<P>(self, migrations: Migrations<P>) -> Result<StructDB, Error>
+    where
+        P: VersionProvider,
+    {
+        let is_new = !self.path.exists();
+        let db = self.build()?;
+
+        if is_new {
+            migrations
+                .version_provider
+                .set_version(&db, migrations.target_version)?;
+        } else {
+            migrations.apply(&db)?;
+        }
+
+        Ok(db)
+    }
+
+    /// Sets the concurrency control of transactions started by the database.
+    pub fn with_transaction_mode(mut self, mode: TransactionMode) -> Self {
+        self.transaction_mode = mode;
+        self
+    }
+
+    pub fn build(mut self) -> Result<StructDB, rocksdb::Error> {
         let mut opts = self.options.clone();
-        let db = Database::open(self.path, &mut opts, self.descriptors)?;
+        let descriptors = std::mem::take(&mut self.descriptors);
+        let db = Database::open(&self.path, &mut opts, descriptors)?;
 
-        Ok(StructDB {
-            db: db,
+        Ok(self.finish(db))
+    }
+
+    fn finish(self, db: Database) -> StructDB {
+        StructDB {
+            db,
             caches: self.caches,
-        })
+            notifiers: Default::default(),
+            shared_topics: Default::default(),
+            locks: Default::default(),
+            watchers: Default::default(),
+            transaction_mode: self.transaction_mode,
+            backup_lock: Default::default(),
+        }
     }
 }
 
 pub struct StructDB {
     pub db: Database,
     pub caches: Caches,
+    notifiers: Mutex<HashMap<String, Arc<Notifier>>>,
+    shared_topics: Mutex<HashMap<String, Arc<dyn Any + Send + Sync>>>,
+    locks: Arc<KeyLocks>,
+    watchers: Arc<Watchers>,
+    transaction_mode: TransactionMode,
+    backup_lock: Mutex<()>,
 }
 
 impl StructDB {
@@ -114,18 +184,138 @@ impl StructDB {
 
     pub fn make_table<T: Table>(&self) -> TableImpl<T> {
         TableImpl::new(self.db.raw.clone(), None)
+            .with_locks(self.locks.clone())
+            .with_watchers(self.watchers.clone())
     }
 
     pub fn make_sharded_table<T: Table>(&self, shard: &String) -> TableImpl<T> {
         TableImpl::new(self.db.raw.clone(), Some(shard))
+            .with_locks(self.locks.clone())
+            .with_watchers(self.watchers.clone())
     }
 
+    pub fn make_typed_table<T: TypedTable>(&self) -> TypedTableImpl<T> {
+        TypedTableImpl::new(self.make_table::<T>())
+    }
+
+    pub fn make_sharded_typed_table<T: TypedTable>(&self, shard: &String) -> TypedTableImpl<T> {
+        TypedTableImpl::new(self.make_sharded_table::<T>(shard))
+    }
+
+    /// Topic handle with its own `next_insert`.
+    ///
+    /// Appends are not coordinated with other handles of the column family, a
+    /// topic must only be appended to through one handle at a time. Use
+    /// `make_shared_topic` for topics with several producers, handles from this
+    /// method may read such a topic but must not append to it.
     pub fn make_topic<T: Topic>(&self) -> TopicImpl<T> {
-        TopicImpl::new(self.make_table::<T>())
+        let table = self.make_table::<T>();
+        let notifier = self.notifier(&table.name);
+        TopicImpl::with_notifier(table, notifier)
     }
 
+    /// Sharded topic handle with its own `next_insert`, see `make_topic`.
     pub fn make_sharded_topic<T: Topic>(&self, shard: &String) -> TopicImpl<T> {
-        TopicImpl::new(self.make_sharded_table::<T>(shard))
+        let table = self.make_sharded_table::<T>(shard);
+        let notifier = self.notifier(&table.name);
+        TopicImpl::with_notifier(table, notifier)
+    }
+
+    /// Table handle which records its changes in the topic `C`, the changelog is
+    /// shared with all other handles of the topic from `make_shared_topic`.
+    pub fn make_changelog_table<T, C>(&self) -> Result<ChangelogTable<T, C>, Error>
+    where
+        T: Table,
+        C: Topic + Send + 'static,
+    {
+        Ok(ChangelogTable::new(
+            self.make_table::<T>(),
+            self.make_shared_topic::<C>()?,
+        ))
+    }
+
+    /// Starts a transaction which writes staged operations atomically on commit.
+    pub fn begin_transaction(&self) -> Transaction<'_> {
+        Transaction::new(
+            &self.db.raw,
+            &self.locks,
+            &self.watchers,
+            self.transaction_mode,
+        )
+    }
+
+    /// Runs `f` and commits its staged operations if it succeeds.
+    ///
+    /// Nothing is written if `f` fails, conflicts surface as `Error::TransactionConflict`.
+    pub fn transaction<R, F>(&self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut Transaction<'_>) -> Result<R, Error>,
+    {
+        let mut txn = self.begin_transaction();
+        let result = f(&mut txn)?;
+        txn.commit()?;
+        Ok(result)
+    }
+
+    /// Runs `f` in a new transaction until it commits without a conflict, at most
+    /// `max_attempts` times. Other errors are returned right away.
+    pub fn transaction_with_retry<R, F>(&self, max_attempts: usize, mut f: F) -> Result<R, Error>
+    where
+        F: FnMut(&mut Transaction<'_>) -> Result<R, Error>,
+    {
+        let mut attempt = 1;
+        loop {
+            match self.transaction(&mut f) {
+                Err(err) if err.is_conflict() && attempt < max_attempts => attempt += 1,
+                result => return result,
+            }
+        }
+    }
+
+    pub fn transaction_mode(&self) -> TransactionMode {
+        self.transaction_mode
+    }
+
+    /// Thread-safe topic handle, all callers get the same handle for the column family.
+    ///
+    /// Fails if the handle of the column family was created for another topic type.
+    pub fn make_shared_topic<T>(&self) -> Result<Arc<SharedTopic<T>>, Error>
+    where
+        T: Topic + Send + 'static,
+    {
+        self.shared_topic(T::NAME.to_string(), || self.make_topic::<T>())
+    }
+
+    pub fn make_sharded_shared_topic<T>(&self, shard: &String) -> Result<Arc<SharedTopic<T>>, Error>
+    where
+        T: Topic + Send + 'static,
+    {
+        let name = format!("{}_{}", T::NAME, shard);
+        self.shared_topic(name, || self.make_sharded_topic::<T>(shard))
+    }
+
+    fn shared_topic<T, F>(&self, name: String, make: F) -> Result<Arc<SharedTopic<T>>, Error>
+    where
+        T: Topic + Send + 'static,
+        F: FnOnce() -> TopicImpl<T>,
+    {
+        let mut shared_topics = self.shared_topics.lock().unwrap();
+        if let Some(topic) = shared_topics.get(&name) {
+            return topic
+                .clone()
+                .downcast::<SharedTopic<T>>()
+                .map_err(|_| Error::SharedTopicTypeMismatch(name));
+        }
+
+        let topic = Arc::new(SharedTopic::new(make()));
+        shared_topics.insert(name, topic.clone());
+        Ok(topic)
+    }
+
+    /// Append notifier shared by all topic handles of the column family.
+    fn notifier(&self, name: &str) -> Arc<Notifier> {
+        let mut notifiers = self.notifiers.lock().unwrap();
+        notifiers.entry(name.to_string()).or_default().clone()
     }
 
     pub fn stats(&self) -> Result<Stats, rocksdb::Error> {
@@ -144,6 +334,69 @@ impl StructDB {
         })
     }
 
+    /// Point in time view of the whole database, tables are read with `DatabaseSnapshot::table`.
+    pub fn snapshot(&self) -> Result<DatabaseSnapshot<'_>, Error> {
+        DatabaseSnapshot::new(&self.db, "default")
+    }
+
+    /// Creates an incremental backup of all column families in `dir`, while the
+    /// database keeps serving reads and writes. Older backups are managed with `Backups`.
+    ///
+    /// Calls on the same database run one at a time. Backups into the same
+    /// directory from other databases, processes or `Backups` handles must not
+    /// run at the same time, keep one `Backups` handle for such directories.
+    pub fn backup_to<P: AsRef<Path>>(&self, dir: P) -> Result<BackupInfo, Error> {
+        let _guard = self
+            .backup_lock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        Backups::open(dir)?.create(self)
+    }
+
+    /// Restores a backup from `dir` into the database at `path`, which must not be open.
+    pub fn restore_from<B, P>(dir: B, backup_id: u32, path: P) -> Result<(), Error>
+    where
+        B: AsRef<Path>,
+        P: AsRef<Path>,
+    {
+        Backups::open(dir)?.restore(backup_id, path)
+    }
+
+    /// Writes an openable copy of the database to `path`, which must not exist.
+    ///
+    /// Table files are hard-linked when `path` is on the same filesystem, so
+    /// the copy is cheap regardless of the database size.
+    pub fn checkpoint<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let checkpoint = rocksdb::checkpoint::Checkpoint::new(self.raw().as_ref())?;
+        checkpoint.create_checkpoint(path)?;
+        Ok(())
+    }
+
+    /// Opens a copy created by `checkpoint` at the path of `builder` with all of
+    /// its column families.
+    ///
+    /// Tables registered with `Builder::with_struct` keep their options, other
+    /// column families use default options. Registered tables missing from the
+    /// copy are created on first use. Fails with `Error::DatabaseNotFound`
+    /// instead of creating an empty database if there is no copy at the path.
+    pub fn open_checkpoint(mut builder: Builder) -> Result<StructDB, Error> {
+        if !builder.path.exists() {
+            return Err(Error::DatabaseNotFound(builder.path.display().to_string()));
+        }
+
+        let existing = Database::list_cf(builder.path.clone())?;
+        builder
+            .descriptors
+            .retain(|cfd| existing.iter().any(|cf| cf == cfd.name()));
+        builder.add_existing_cfs()?;
+
+        let mut opts = builder.options.clone();
+        let descriptors = std::mem::take(&mut builder.descriptors);
+        let db = Database::open_existing(&builder.path, &mut opts, descriptors)?;
+
+        Ok(builder.finish(db))
+    }
+
     #[inline]
     pub fn raw(&self) -> &Arc<rocksdb::DB> {
         &self.db.raw
@@ -159,9 +412,15 @@ impl StructDB {
 mod tests {
     use std::fs;
 
-    use crate::{caches::Caches, table::Table};
+    use crate::{
+        caches::Caches,
+        errors::Error,
+        handle::Migrations,
+        merge::{Aggregate, CounterTable},
+        table::Table,
+    };
 
-    use super::StructDB;
+    use super::{DefaultVersionProvider, StructDB, VersionProvider};
 
     struct MyTable;
 
@@ -210,4 +469,140 @@ mod tests {
             .build()
             .unwrap();
     }
+
+    #[test]
+    fn test_build_with_migrations() {
+        let _ = fs::remove_dir_all("test_build_with_migrations.db");
+        let provider = DefaultVersionProvider;
+
+        {
+            let migrations = Migrations::with_target_version([0, 1, 0]);
+            let db = StructDB::builder("test_build_with_migrations.db", Caches::default())
+                .build_with_migrations(migrations)
+                .unwrap();
+            assert_eq!(provider.get_version(&db).unwrap(), Some([0, 1, 0]));
+        }
+
+        {
+            let mut migrations = Migrations::with_target_version([0, 3, 0]);
+            migrations
+                .register([0, 1, 0], [0, 2, 0], |db| {
+                    db.make_table::<MyTable>().insert("migrated", "0.2.0")?;
+                    Ok(())
+                })
+                .unwrap();
+
+            let result = StructDB::builder("test_build_with_migrations.db", Caches::default())
+                .build_with_migrations(migrations);
+            assert!(matches!(result, Err(Error::MigrationNotFound([0, 2, 0]))));
+        }
+
+        {
+            let mut migrations = Migrations::with_target_version([0, 3, 0]);
+            migrations
+                .register([0, 2, 0], [0, 3, 0], |db| {
+                    db.make_table::<MyTable>().insert("migrated", "0.3.0")?;
+                    Ok(())
+                })
+                .unwrap();
+
+            let db = StructDB::builder("test_build_with_migrations.db", Caches::default())
+                .with_struct::<MyTable>()
+                .build_with_migrations(migrations)
+                .unwrap();
+            assert_eq!(provider.get_version(&db).unwrap(), Some([0, 3, 0]));
+
+            let table = db.make_table::<MyTable>();
+            assert_eq!(table.get("migrated").unwrap().unwrap().as_ref(), b"0.3.0");
+        }
+
+        {
+            let migrations = Migrations::with_target_version([0, 2, 0]);
+            let result = StructDB::builder("test_build_with_migrations.db", Caches::default())
+                .with_struct::<MyTable>()
+                .build_with_migrations(migrations);
+            assert!(matches!(
+                result,
+                Err(Error::IncompatibleDbVersion {
+                    version: [0, 3, 0],
+                    expected: [0, 2, 0]
+                })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_build_with_migrations_version_not_found() {
+        let _ = fs::remove_dir_all("test_build_with_migrations_version_not_found.db");
+
+        {
+            let _db = StructDB::builder(
+                "test_build_with_migrations_version_not_found.db",
+                Caches::default(),
+            )
+            .build()
+            .unwrap();
+        }
+
+        let migrations = Migrations::with_target_version([0, 1, 0]);
+        let result = StructDB::builder(
+            "test_build_with_migrations_version_not_found.db",
+            Caches::default(),
+        )
+        .build_with_migrations(migrations);
+        assert!(matches!(result, Err(Error::VersionNotFound)));
+    }
+
+    struct Visits;
+
+    impl Table for Visits {
+        const NAME: &'static str = "visits";
+
+        fn options(opts: &mut rocksdb::Options, _caches: &Caches) {
+            Aggregate::Counter.register(opts);
+        }
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let _ = fs::remove_dir_all("test_checkpoint.db");
+        let _ = fs::remove_dir_all("test_checkpoint.copy");
+        let db = StructDB::builder("test_checkpoint.db", Caches::default())
+            .with_struct::<MyTable>()
+            .with_struct::<Visits>()
+            .build()
+            .unwrap();
+
+        let table = db.make_table::<MyTable>();
+        table.insert("key", "before").unwrap();
+        CounterTable::new(db.make_table::<Visits>())
+            .add("home", 3)
+            .unwrap();
+        db.checkpoint("test_checkpoint.copy").unwrap();
+        table.insert("key", "after").unwrap();
+        assert!(db.checkpoint("test_checkpoint.copy").is_err());
+
+        // NOTE: Copy keeps the state at checkpoint time and is written independently.
+        let copy = StructDB::open_checkpoint(
+            StructDB::builder("test_checkpoint.copy", Caches::default())
+                .with_struct::<MyTable>()
+                .with_struct::<Visits>(),
+        )
+        .unwrap();
+        let copied = copy.make_table::<MyTable>();
+        assert_eq!(copied.get("key").unwrap().unwrap().as_ref(), b"before");
+        copied.insert("key", "copy").unwrap();
+        assert_eq!(table.get("key").unwrap().unwrap().as_ref(), b"after");
+
+        // NOTE: Registered tables keep their options, the merge operator reads the counter.
+        let visits = CounterTable::new(copy.make_table::<Visits>());
+        assert_eq!(visits.get("home").unwrap(), 3);
+
+        let missing = StructDB::open_checkpoint(StructDB::builder(
+            "test_checkpoint.missing",
+            Caches::default(),
+        ));
+        assert!(matches!(missing, Err(Error::DatabaseNotFound(_))));
+        assert!(!std::path::Path::new("test_checkpoint.missing").exists());
+    }
 }
@@ -0,0 +1,258 @@
+use std::{sync::Arc, time::Duration};
+
+use byte_counter::counter::ByteCounter;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    batch::Batch,
+    errors::Result,
+    iterator_batch::{BatchIterator, CommitMode, IteratorBatch},
+    record::SeqRecord,
+    serialization::BinCode,
+    shared_topic::SharedTopic,
+    table::{Table, TableImpl},
+    timestamp::epoch_ns,
+    topic::{Topic, TopicImpl},
+};
+
+/// Insert or remove of a key, as recorded in a changelog topic.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    /// Key of the changelog record, increases with every change.
+    pub seq: ByteCounter,
+    /// Time of the change in epoch nanoseconds.
+    pub timestamp: u128,
+    pub key: Vec<u8>,
+    /// Value before the change, `None` if the key did not exist.
+    pub old: Option<Vec<u8>>,
+    /// Value after the change, `None` if the key was removed.
+    pub new: Option<Vec<u8>>,
+}
+
+impl BinCode for Change {}
+
+impl Change {
+    pub fn is_remove(&self) -> bool {
+        self.new.is_none()
+    }
+}
+
+/// Table handle which records every insert and remove in a changelog topic.
+///
+/// The row and its change are written in one write batch while the changelog
+/// is locked, so changes are stored in the order they were applied. Writes which
+/// bypass this handle, e.g. through `table` directly, are not recorded.
+pub struct ChangelogTable<T, C> {
+    pub table: TableImpl<T>,
+    pub changelog: Arc<SharedTopic<C>>,
+}
+
+impl<T, C> ChangelogTable<T, C>
+where
+    T: Table,
+    C: Topic,
+{
+    pub fn new(table: TableImpl<T>, changelog: Arc<SharedTopic<C>>) -> Self {
+        Self { table, changelog }
+    }
+
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>> {
+        Ok(self.table.get(key)?.map(|value| value.to_vec()))
+    }
+
+    pub fn insert<K, V>(&self, key: K, value: V) -> Result<Change>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        self.write(key.as_ref(), Some(value.as_ref()))
+    }
+
+    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Result<Change> {
+        self.write(key.as_ref(), None)
+    }
+
+    fn write(&self, key: &[u8], new: Option<&[u8]>) -> Result<Change> {
+        let mut changelog = self.changelog.lock();
+
+        let change = Change {
+            seq: changelog.next_insert.clone(),
+            timestamp: epoch_ns(),
+            key: key.to_vec(),
+            old: self.get(key)?,
+            new: new.map(|new| new.to_vec()),
+        };
+        let record = SeqRecord::new(change.seq.clone(), change.to_bytes()?);
+
+        let mut batch = Batch::default();
+        match new {
+            Some(new) => batch.put(&self.table, key, new),
+            None => batch.delete(&self.table, key),
+        }
+        changelog.stage_records(&mut batch, std::slice::from_ref(&record));
+        self.table.apply(batch)?;
+
+        changelog.next_insert = changelog.next_insert.next_id();
+        changelog.notifier.notify();
+        Ok(change)
+    }
+}
+
+/// Named consumer of a changelog topic which resumes from its committed checkpoint.
+///
+/// Changes are delivered at least once, `ack` persists the last delivered change.
+pub struct ChangeFeed<'a, C>
+where
+    C: Table + 'a,
+{
+    inner: IteratorBatch<'a, C>,
+}
+
+impl<'a, C> ChangeFeed<'a, C>
+where
+    C: Topic,
+{
+    pub fn new(changelog: &'a TopicImpl<C>, name: &str, batch_size: usize) -> Self {
+        Self {
+            inner: changelog.window_with_commit(name, batch_size, CommitMode::Manual),
+        }
+    }
+
+    /// Creates a feed which starts at the change with the given sequence number
+    /// instead of its checkpoint.
+    pub fn from_seq(
+        changelog: &'a TopicImpl<C>,
+        name: &str,
+        batch_size: usize,
+        seq: &ByteCounter,
+    ) -> Self {
+        Self {
+            inner: IteratorBatch::from_key(
+                Box::new(changelog),
+                name,
+                batch_size,
+                CommitMode::Manual,
+                seq,
+            ),
+        }
+    }
+
+    pub fn next(&mut self) -> Result<Vec<Change>> {
+        decode_changes(self.inner.next()?)
+    }
+
+    /// Returns the next changes, waiting up to `timeout` for new ones.
+    pub fn next_timeout(&mut self, timeout: Duration) -> Result<Vec<Change>> {
+        decode_changes(self.inner.next_timeout(timeout)?)
+    }
+
+    /// Commits the last change handed out by `next`.
+    pub fn ack(&mut self) -> Result<()> {
+        self.inner.ack()
+    }
+
+    /// Persists the given sequence number as the last processed change.
+    pub fn commit(&mut self, seq: &ByteCounter) -> Result<()> {
+        self.inner.commit(seq)
+    }
+
+    /// Number of changes after the committed checkpoint, up to the last change
+    /// written to the changelog.
+    pub fn lag(&self) -> u128 {
+        self.inner.tail_distance()
+    }
+}
+
+fn decode_changes(records: Vec<SeqRecord>) -> Result<Vec<Change>> {
+    records
+        .iter()
+        .map(|record| Change::from_bytes(&record.value))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use byte_counter::{counter::ByteCounter, timestamp::Timestamp};
+
+    use crate::{
+        builder::StructDB,
+        caches::Caches,
+        table::Table,
+        topic::{Topic, TOPIC_KEY_PREFIX},
+    };
+
+    use super::ChangeFeed;
+
+    struct Products;
+
+    impl Table for Products {
+        const NAME: &'static str = "products";
+    }
+
+    struct ProductChanges;
+
+    impl Table for ProductChanges {
+        const NAME: &'static str = "product-changes";
+    }
+
+    impl Topic for ProductChanges {}
+
+    #[test]
+    fn test_changelog() {
+        let _ = fs::remove_dir_all("test_changelog.db");
+        let db = StructDB::builder("test_changelog.db", Caches::default())
+            .with_struct::<Products>()
+            .with_struct::<ProductChanges>()
+            .build()
+            .unwrap();
+
+        let products = db
+            .make_changelog_table::<Products, ProductChanges>()
+            .unwrap();
+        products.insert("p1", "red").unwrap();
+        products.insert("p1", "blue").unwrap();
+        let removed = products.remove("p1").unwrap();
+        assert!(removed.is_remove());
+        assert_eq!(products.get("p1").unwrap(), None);
+
+        let changelog = products.changelog.reader();
+        let mut feed = ChangeFeed::new(&changelog, "search", 10);
+        let changes = feed.next().unwrap();
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[0].old, None);
+        assert_eq!(changes[0].new, Some(b"red".to_vec()));
+        assert_eq!(changes[1].old, Some(b"red".to_vec()));
+        assert_eq!(changes[1].new, Some(b"blue".to_vec()));
+        assert_eq!(changes[2], removed);
+        assert!(changes
+            .windows(2)
+            .all(|w| w[0].seq.to_u128() < w[1].seq.to_u128()));
+
+        // NOTE: Only acked changes are skipped by a feed with the same name.
+        let mut feed = ChangeFeed::new(&changelog, "search", 10);
+        assert_eq!(feed.next().unwrap().len(), 3);
+        feed.ack().unwrap();
+
+        products.insert("p2", "green").unwrap();
+        let changelog = products.changelog.reader();
+        let mut feed = ChangeFeed::new(&changelog, "search", 10);
+        assert_eq!(feed.lag(), 1);
+        let changes = feed.next().unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].key, b"p2".to_vec());
+
+        let mut replay = ChangeFeed::from_seq(&changelog, "replay", 10, &removed.seq);
+        assert_eq!(replay.next().unwrap().len(), 2);
+
+        // NOTE: Sequence numbers rebuilt without their timestamp seek to the same change.
+        for second in [1_000_000_000, removed.seq.timestamp.value() + 3600] {
+            let mut seq = ByteCounter::new_with_prefix(TOPIC_KEY_PREFIX.to_string());
+            seq.id = removed.seq.id;
+            seq.timestamp = Timestamp::from(second.to_string().as_str());
+            let mut replay = ChangeFeed::from_seq(&changelog, "rebuilt", 10, &seq);
+            assert_eq!(replay.next().unwrap()[0], removed);
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use byte_counter::counter::ByteCounter;
+
+use crate::{
+    errors::Result,
+    iterator_batch::{iter_checkpoint, iter_checkpoint_key, lag, parse_checkpoint},
+    record::SeqRecord,
+    table::Table,
+    topic::{TopicImpl, TOPIC_ITERATOR_KEY_PREFIX},
+};
+
+/// State of a named iterator of a topic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsumerInfo {
+    pub name: String,
+    /// Key of the last committed record, `None` if the checkpoint can not be parsed.
+    pub position: Option<ByteCounter>,
+    /// Records between the checkpoint and the end of the topic, same as `tail_distance`.
+    pub lag: u128,
+}
+
+/// Registry of the named iterators of a topic.
+///
+/// Changes are written to the checkpoints only, iterators which are alive keep
+/// their in-memory position and pick up a reset when they are created again.
+pub struct Consumers<'a, T>
+where
+    T: Table + 'a,
+{
+    pub topic: &'a TopicImpl<T>,
+}
+
+impl<'a, T> Consumers<'a, T>
+where
+    T: Table,
+{
+    pub fn new(topic: &'a TopicImpl<T>) -> Self {
+        Self { topic }
+    }
+
+    /// Lists all named iterators which have committed a checkpoint, ordered by name.
+    pub fn list(&self) -> Result<Vec<ConsumerInfo>> {
+        let prefix = format!("{}:", TOPIC_ITERATOR_KEY_PREFIX);
+        let mut result = vec![];
+
+        let mut iter = self.topic.table.prefix_iterator(&prefix);
+        loop {
+            if !iter.valid() {
+                break;
+            }
+            let (key, value) = match iter.item() {
+                Some(item) => item,
+                None => break,
+            };
+            if !key.starts_with(prefix.as_bytes()) {
+                break;
+            }
+
+            let name = String::from_utf8_lossy(&key[prefix.len()..]).to_string();
+            result.push(self.info(name, parse_checkpoint(value)));
+            iter.next();
+        }
+        iter.status()?;
+
+        Ok(result)
+    }
+
+    pub fn get(&self, name: &str) -> Result<Option<ConsumerInfo>> {
+        let key = iter_checkpoint_key(name);
+        if !self.topic.table.contains_key(key.as_bytes())? {
+            return Ok(None);
+        }
+
+        Ok(Some(
+            self.info(name.to_string(), iter_checkpoint(name, self.topic)),
+        ))
+    }
+
+    /// Rewinds the iterator so the next batch starts at the oldest retained record.
+    pub fn reset_to_start(&self, name: &str) -> Result<()> {
+        let first = {
+            let iter = self.topic.iter_after(None);
+            let record = SeqRecord::from(iter.item());
+            if iter.valid() && record.is_valid() {
+                Some(record.key)
+            } else {
+                None
+            }
+        };
+
+        match first {
+            Some(first) => {
+                // NOTE: `prev_id` resets the timestamp, keep the one of the first record
+                // so the checkpoint sorts right before it.
+                let mut before = first.clone();
+                before.id = first.prev_id().id;
+                self.reset_to(name, &before)
+            }
+            None => self.delete(name),
+        }
+    }
+
+    /// Moves the iterator to the end of the topic, only records appended later are read.
+    pub fn reset_to_end(&self, name: &str) -> Result<()> {
+        match self.topic.last_insert() {
+            Some(last) => self.reset_to(name, &last),
+            None => self.delete(name),
+        }
+    }
+
+    /// Moves the iterator so the next batch starts with the first record after `key`.
+    pub fn reset_to(&self, name: &str, key: &ByteCounter) -> Result<()> {
+        let checkpoint = iter_checkpoint_key(name);
+        let value = self.topic.checkpoint_key(key);
+
+        self.topic
+            .table
+            .insert(checkpoint.as_bytes(), value.as_bytes())?;
+        Ok(())
+    }
+
+    /// Removes the checkpoint, a new iterator with the same name starts at the beginning.
+    pub fn delete(&self, name: &str) -> Result<()> {
+        let checkpoint = iter_checkpoint_key(name);
+        self.topic.table.remove(checkpoint.as_bytes())?;
+        Ok(())
+    }
+
+    fn info(&self, name: String, position: Option<ByteCounter>) -> ConsumerInfo {
+        let lag = lag(self.topic, position.as_ref());
+        ConsumerInfo {
+            name,
+            position,
+            lag,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use byte_counter::timestamp::Timestamp;
+
+    use crate::{
+        builder::StructDB, caches::Caches, iterator_batch::BatchIterator, table::Table,
+        topic::Topic,
+    };
+
+    struct MyTopic;
+
+    impl Table for MyTopic {
+        const NAME: &'static str = "my-topic";
+    }
+
+    impl Topic for MyTopic {}
+
+    #[test]
+    fn test_consumers() {
+        let _ = fs::remove_dir_all("test_consumers.db");
+        let db = StructDB::builder("test_consumers.db", Caches::default())
+            .with_struct::<MyTopic>()
+            .build()
+            .unwrap();
+
+        let mut topic = db.make_topic::<MyTopic>();
+        for _ in 0..20 {
+            topic.append(&vec![1; 32]).unwrap();
+        }
+
+        let tail_distance = {
+            let mut iter1 = topic.window("iter1", 5);
+            iter1.next().unwrap();
+            iter1.tail_distance()
+        };
+        topic.window("iter2", 15).next().unwrap();
+
+        let consumers = topic.consumers();
+        let list = consumers.list().unwrap();
+        let names: Vec<&str> = list.iter().map(|info| info.name.as_str()).collect();
+        assert_eq!(names, vec!["iter1", "iter2"]);
+        assert_eq!(list[0].position.as_ref().unwrap().to_u128(), 5);
+        assert_eq!(list[0].lag, tail_distance);
+        assert_eq!(list[0].lag, 15);
+        assert_eq!(list[1].lag, 5);
+        assert!(consumers.get("unknown").unwrap().is_none());
+
+        consumers.reset_to_end("iter1").unwrap();
+        assert_eq!(consumers.get("iter1").unwrap().unwrap().lag, 0);
+        assert!(topic.window("iter1", 5).next().unwrap().is_empty());
+
+        consumers.reset_to_start("iter1").unwrap();
+        let batch = topic.window("iter1", 5).next().unwrap();
+        assert_eq!(batch[0].key.to_u128(), 1);
+
+        let key = batch[2].key.clone();
+        consumers.reset_to("iter2", &key).unwrap();
+        let batch = topic.window("iter2", 5).next().unwrap();
+        assert_eq!(batch[0].key.to_u128(), 4);
+
+        // NOTE: Counters rebuilt with another timestamp are stored as the key of their record.
+        let mut rebuilt = key.clone();
+        rebuilt.timestamp = Timestamp::from("0");
+        consumers.reset_to("iter2", &rebuilt).unwrap();
+        let batch = topic.window("iter2", 5).next().unwrap();
+        assert_eq!(batch[0].key.to_u128(), 4);
+
+        consumers.delete("iter2").unwrap();
+        assert!(consumers.get("iter2").unwrap().is_none());
+        assert_eq!(consumers.list().unwrap().len(), 1);
+    }
+}
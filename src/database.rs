@@ -1,4 +1,7 @@
-use std::{path::Path, sync::Arc};
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, Weak},
+};
 
 use crate::errors::{Error, Result, RocksResult};
 use rocksdb::{
@@ -19,6 +22,9 @@ pub enum DBCompressionTypeDef {
     Zstd,
 }
 
+/// Databases opened by this process, an entry ends with the last handle of its database.
+static OPEN_DATABASES: Mutex<Vec<(PathBuf, Weak<rocksdb::DB>)>> = Mutex::new(Vec::new());
+
 pub struct Database {
     pub raw: Arc<rocksdb::DB>,
     options: rocksdb::Options,
@@ -34,7 +40,33 @@ impl Database {
         options.create_if_missing(true);
         options.create_missing_column_families(true);
 
-        let db = Arc::new(rocksdb::DB::open_cf_descriptors(options, path, cfd)?);
+        Self::open_with(path, options, cfd)
+    }
+
+    /// Opens an existing database, fails instead of creating it or any of the column families.
+    pub fn open_existing<P: AsRef<Path>, I: IntoIterator<Item = ColumnFamilyDescriptor>>(
+        path: P,
+        options: &mut rocksdb::Options,
+        cfd: I,
+    ) -> RocksResult<Self> {
+        options.create_if_missing(false);
+        options.create_missing_column_families(false);
+
+        Self::open_with(path, options, cfd)
+    }
+
+    fn open_with<P: AsRef<Path>, I: IntoIterator<Item = ColumnFamilyDescriptor>>(
+        path: P,
+        options: &mut rocksdb::Options,
+        cfd: I,
+    ) -> RocksResult<Self> {
+        let db = Arc::new(rocksdb::DB::open_cf_descriptors(options, &path, cfd)?);
+
+        if let Ok(path) = path.as_ref().canonicalize() {
+            let mut open = OPEN_DATABASES.lock().unwrap();
+            open.retain(|(_, db)| db.strong_count() > 0);
+            open.push((path, Arc::downgrade(&db)));
+        }
 
         Ok(Database {
             raw: db,
@@ -42,6 +74,22 @@ impl Database {
         })
     }
 
+    /// Checks if a database at `path` is open in this process.
+    ///
+    /// Databases opened by other processes are not detected.
+    pub fn is_open<P: AsRef<Path>>(path: P) -> bool {
+        let path = match path.as_ref().canonicalize() {
+            Ok(path) => path,
+            Err(_) => return false,
+        };
+
+        OPEN_DATABASES
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(open, db)| *open == path && db.strong_count() > 0)
+    }
+
     pub fn list_cf<P: AsRef<Path>>(path: P) -> RocksResult<Vec<String>> {
         let result = rocksdb::DB::list_cf(&rocksdb::Options::default(), path)?;
         Ok(result)
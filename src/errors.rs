@@ -23,6 +23,26 @@ pub enum Error {
     SerializationFailed(String),
     #[error("deserialization failed")]
     DeserializationFailed(String),
+    #[error("transaction conflict: {0}")]
+    TransactionConflict(String),
+    #[error("unique index violation: {0}")]
+    UniqueIndexViolation(String),
+    #[error("shared topic has another type: {0}")]
+    SharedTopicTypeMismatch(String),
+    #[error("backup not found")]
+    BackupNotFound,
+    #[error("database is open: {0}")]
+    DatabaseOpen(String),
+    #[error("database not found: {0}")]
+    DatabaseNotFound(String),
+}
+
+impl Error {
+    /// Returns `true` if the operation failed because of a concurrent transaction
+    /// and can be retried.
+    pub fn is_conflict(&self) -> bool {
+        matches!(self, Error::TransactionConflict(_))
+    }
 }
 
 pub type Result<I> = std::result::Result<I, Error>;
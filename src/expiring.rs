@@ -0,0 +1,255 @@
+use std::{marker::PhantomData, sync::mpsc::Receiver, time::Duration};
+
+use rocksdb::compaction_filter::Decision;
+
+use crate::{
+    errors::{Error, Result},
+    table::{Table, TableImpl},
+    timestamp::epoch_millis,
+    watch::WatchEvent,
+};
+
+/// Length of the expiry time stored in front of every value.
+const EXPIRY_LEN: usize = 8;
+/// Expiry time of entries which never expire.
+const NEVER: u64 = 0;
+
+/// Table whose entries expire after a time to live.
+///
+/// Values are stored behind their expiry time in epoch milliseconds, expired
+/// entries are hidden from reads and dropped by the compaction filter which is
+/// registered from `Table::options` with `register_compaction_filter`.
+pub trait ExpiringTable: Table {
+    /// Time to live of entries inserted without an explicit one, `None` keeps them forever.
+    const DEFAULT_TTL: Option<Duration> = None;
+}
+
+/// Drops expired entries of the column family during compaction.
+pub fn register_compaction_filter(opts: &mut rocksdb::Options) {
+    opts.set_compaction_filter("structdb.ttl", |_level: u32, _key: &[u8], value: &[u8]| {
+        match expires_at(value) {
+            Some(expires_at) if is_expired(expires_at, epoch_millis()) => Decision::Remove,
+            _ => Decision::Keep,
+        }
+    });
+}
+
+fn expires_at(value: &[u8]) -> Option<u64> {
+    Some(u64::from_be_bytes(
+        value.get(..EXPIRY_LEN)?.try_into().ok()?,
+    ))
+}
+
+fn is_expired(expires_at: u64, now: u64) -> bool {
+    expires_at != NEVER && expires_at <= now
+}
+
+fn encode_value(value: &[u8], ttl: Option<Duration>) -> Vec<u8> {
+    let expires_at = match ttl {
+        Some(ttl) => epoch_millis().saturating_add(ttl.as_millis() as u64),
+        None => NEVER,
+    };
+
+    let mut encoded = Vec::with_capacity(EXPIRY_LEN + value.len());
+    encoded.extend_from_slice(&expires_at.to_be_bytes());
+    encoded.extend_from_slice(value);
+    encoded
+}
+
+/// Strips the expiry time, `None` if the entry has expired at `now`.
+fn decode_value(table: &str, encoded: &[u8], now: u64) -> Result<Option<Vec<u8>>> {
+    let expires_at = expires_at(encoded)
+        .ok_or_else(|| Error::DeserializationFailed(format!("missing expiry time in {}", table)))?;
+
+    if is_expired(expires_at, now) {
+        return Ok(None);
+    }
+    Ok(Some(encoded[EXPIRY_LEN..].to_vec()))
+}
+
+/// Strips the expiry time of a written value for watchers.
+fn strip_expiry(encoded: &[u8]) -> Vec<u8> {
+    encoded.get(EXPIRY_LEN..).unwrap_or_default().to_vec()
+}
+
+pub struct ExpiringTableImpl<T> {
+    pub table: TableImpl<T>,
+}
+
+impl<T> ExpiringTableImpl<T>
+where
+    T: ExpiringTable,
+{
+    pub fn new(table: TableImpl<T>) -> Self {
+        Self { table }
+    }
+
+    /// Inserts the value with the default time to live of the table.
+    pub fn insert<K, V>(&self, key: K, value: V) -> Result<()>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        self.insert_with_ttl(key, value, T::DEFAULT_TTL)
+    }
+
+    /// Inserts the value with the given time to live, `None` keeps it forever.
+    pub fn insert_with_ttl<K, V>(&self, key: K, value: V, ttl: Option<Duration>) -> Result<()>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        self.table
+            .insert(key, encode_value(value.as_ref(), ttl))
+            .map_err(Into::into)
+    }
+
+    /// Returns the value unless it is missing or has expired.
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>> {
+        match self.table.get(key)? {
+            Some(encoded) => decode_value(&self.table.name, &encoded, epoch_millis()),
+            None => Ok(None),
+        }
+    }
+
+    /// Remaining time to live of the entry, `None` if it is missing, expired or never expires.
+    pub fn ttl<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Duration>> {
+        let encoded = match self.table.get(key)? {
+            Some(encoded) => encoded,
+            None => return Ok(None),
+        };
+
+        let now = epoch_millis();
+        match expires_at(&encoded) {
+            Some(expires_at) if expires_at != NEVER && expires_at > now => {
+                Ok(Some(Duration::from_millis(expires_at - now)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Result<()> {
+        self.table.remove(key).map_err(Into::into)
+    }
+
+    /// Receives every write of the key with the value as it was inserted, see `TableImpl::watch`.
+    pub fn watch<K: AsRef<[u8]>>(&self, key: K) -> Receiver<WatchEvent> {
+        self.table.watch_decoded(key.as_ref(), strip_expiry)
+    }
+
+    /// Receives every write of keys starting with `prefix`, see `watch`.
+    pub fn watch_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Receiver<WatchEvent> {
+        self.table
+            .watch_prefix_decoded(prefix.as_ref(), strip_expiry)
+    }
+
+    pub fn iter_start(&'_ self) -> ExpiringIterator<'_, T> {
+        ExpiringIterator::new(&self.table.name, self.table.iter_start())
+    }
+
+    /// Iterates over keys in `[from, to)`, either bound can be omitted.
+    pub fn iter_range(&'_ self, from: Option<&[u8]>, to: Option<&[u8]>) -> ExpiringIterator<'_, T> {
+        ExpiringIterator::new(&self.table.name, self.table.iter_range(from, to))
+    }
+}
+
+/// Iterator over the entries of an expiring table which skips expired entries.
+///
+/// Entries are checked against the time the iterator was created.
+pub struct ExpiringIterator<'a, T> {
+    name: &'a str,
+    inner: rocksdb::DBIterator<'a>,
+    now: u64,
+    _ty: PhantomData<T>,
+}
+
+impl<'a, T> ExpiringIterator<'a, T> {
+    fn new(name: &'a str, inner: rocksdb::DBIterator<'a>) -> Self {
+        Self {
+            name,
+            inner,
+            now: epoch_millis(),
+            _ty: Default::default(),
+        }
+    }
+}
+
+impl<'a, T> Iterator for ExpiringIterator<'a, T> {
+    type Item = Result<(Box<[u8]>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (key, encoded) = match self.inner.next()? {
+                Ok(item) => item,
+                Err(err) => return Some(Err(err.into())),
+            };
+
+            match decode_value(self.name, &encoded, self.now) {
+                Ok(Some(value)) => return Some(Ok((key, value))),
+                Ok(None) => continue,
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, thread, time::Duration};
+
+    use crate::{builder::StructDB, caches::Caches, table::Table};
+
+    use super::{register_compaction_filter, ExpiringTable, ExpiringTableImpl};
+
+    struct Sessions;
+
+    impl Table for Sessions {
+        const NAME: &'static str = "sessions";
+
+        fn options(opts: &mut rocksdb::Options, _caches: &Caches) {
+            register_compaction_filter(opts);
+        }
+    }
+
+    impl ExpiringTable for Sessions {
+        const DEFAULT_TTL: Option<Duration> = Some(Duration::from_millis(200));
+    }
+
+    #[test]
+    fn test_expiring_table() {
+        let _ = fs::remove_dir_all("test_expiring_table.db");
+        let db = StructDB::builder("test_expiring_table.db", Caches::default())
+            .with_struct::<Sessions>()
+            .build()
+            .unwrap();
+
+        let sessions = ExpiringTableImpl::new(db.make_table::<Sessions>());
+        let watched = sessions.watch("short");
+        sessions.insert("short", "a").unwrap();
+        assert_eq!(watched.try_recv().unwrap().value, Some(b"a".to_vec()));
+        sessions
+            .insert_with_ttl("long", "b", Some(Duration::from_secs(3600)))
+            .unwrap();
+        sessions.insert_with_ttl("forever", "c", None).unwrap();
+
+        assert_eq!(sessions.get("short").unwrap(), Some(b"a".to_vec()));
+        assert!(sessions.ttl("long").unwrap().unwrap() > Duration::from_secs(3500));
+        assert_eq!(sessions.ttl("forever").unwrap(), None);
+        assert_eq!(sessions.iter_start().count(), 3);
+
+        thread::sleep(Duration::from_millis(300));
+
+        assert_eq!(sessions.get("short").unwrap(), None);
+        let keys: Vec<Box<[u8]>> = sessions.iter_start().map(|item| item.unwrap().0).collect();
+        assert_eq!(
+            keys,
+            vec![b"forever".to_vec().into(), b"long".to_vec().into()]
+        );
+
+        // NOTE: Expired entries are hidden right away and physically dropped by compaction.
+        assert!(sessions.table.contains_key("short").unwrap());
+        sessions.table.compact_range(None, None);
+        assert!(!sessions.table.contains_key("short").unwrap());
+        assert!(sessions.table.contains_key("long").unwrap());
+    }
+}
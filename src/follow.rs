@@ -0,0 +1,236 @@
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::{Condvar, Mutex},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use futures::Stream;
+
+use crate::{record::SeqRecord, table::Table, topic::TopicImpl};
+
+/// Number of records read from the topic per stream refill.
+const STREAM_CHUNK_SIZE: usize = 128;
+
+/// Signals readers of a topic that new records were appended.
+///
+/// Every append bumps the version, blocked readers wait on the condition
+/// variable and async readers register their wakers.
+#[derive(Default)]
+pub struct Notifier {
+    version: Mutex<u64>,
+    condvar: Condvar,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl Notifier {
+    pub fn version(&self) -> u64 {
+        *self.version.lock().unwrap()
+    }
+
+    /// Wakes all blocked and pending readers.
+    pub fn notify(&self) {
+        {
+            let mut version = self.version.lock().unwrap();
+            *version = version.wrapping_add(1);
+            self.condvar.notify_all();
+        }
+
+        let wakers = std::mem::take(&mut *self.wakers.lock().unwrap());
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Blocks until the version differs from `seen` or the timeout elapses.
+    ///
+    /// Returns `true` if new records were appended in the meantime.
+    pub fn wait(&self, seen: u64, timeout: Duration) -> bool {
+        let version = self.version.lock().unwrap();
+        let (version, _) = self
+            .condvar
+            .wait_timeout_while(version, timeout, |version| *version == seen)
+            .unwrap();
+        *version != seen
+    }
+
+    /// Registers a waker which is woken on the next append.
+    pub fn register(&self, waker: &Waker) {
+        let mut wakers = self.wakers.lock().unwrap();
+        if !wakers.iter().any(|registered| registered.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    }
+}
+
+/// Endless stream of topic records which waits for new appends at the end of the topic.
+///
+/// The stream keeps only the key of the last delivered record between polls,
+/// each refill reads a chunk of records with a fresh iterator.
+pub struct TopicStream<'a, T>
+where
+    T: Table + 'a,
+{
+    pub topic: &'a TopicImpl<T>,
+    last: Option<Vec<u8>>,
+    buffer: VecDeque<SeqRecord>,
+}
+
+impl<'a, T> TopicStream<'a, T>
+where
+    T: Table,
+{
+    /// Creates a stream which starts after the record with the given key or at the
+    /// beginning of the topic.
+    pub fn new(topic: &'a TopicImpl<T>, after: Option<Vec<u8>>) -> Self {
+        Self {
+            topic,
+            last: after,
+            buffer: VecDeque::new(),
+        }
+    }
+
+    fn fill(&mut self) {
+        let topic = self.topic;
+        let mut iter = topic.iter_after(self.last.as_deref());
+        while self.buffer.len() < STREAM_CHUNK_SIZE {
+            if !iter.valid() {
+                break;
+            }
+            let item = iter.item();
+            if item.is_none() {
+                break;
+            }
+
+            let record = SeqRecord::from(item);
+            if !record.is_valid() {
+                break;
+            }
+
+            self.buffer.push_back(record);
+            iter.next();
+        }
+
+        if let Some(last) = self.buffer.back() {
+            self.last = Some(last.key.to_string().into_bytes());
+        }
+    }
+}
+
+impl<'a, T> Stream for TopicStream<'a, T>
+where
+    T: Table,
+{
+    type Item = SeqRecord;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            if let Some(record) = this.buffer.pop_front() {
+                return Poll::Ready(Some(record));
+            }
+
+            let seen = this.topic.notifier.version();
+            this.fill();
+            if !this.buffer.is_empty() {
+                continue;
+            }
+
+            // NOTE: Version is checked again after registering so an append in between is not missed.
+            this.topic.notifier.register(cx.waker());
+            if this.topic.notifier.version() == seen {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, thread, time::Duration};
+
+    use futures::StreamExt;
+
+    use crate::{
+        builder::StructDB, caches::Caches, iterator_batch::BatchIterator, table::Table,
+        topic::Topic,
+    };
+
+    struct MyTopic;
+
+    impl Table for MyTopic {
+        const NAME: &'static str = "my-topic";
+    }
+
+    impl Topic for MyTopic {}
+
+    fn append_later(db: &StructDB, count: usize) {
+        thread::sleep(Duration::from_millis(50));
+        let mut topic = db.make_topic::<MyTopic>();
+        for _ in 0..count {
+            topic.append(&vec![1; 32]).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_follow_blocking() {
+        let _ = fs::remove_dir_all("test_follow_blocking.db");
+        let db = StructDB::builder("test_follow_blocking.db", Caches::default())
+            .with_struct::<MyTopic>()
+            .build()
+            .unwrap();
+
+        let topic = db.make_topic::<MyTopic>();
+        let mut iter = topic.iter();
+        let mut window = topic.window("iter1", 10);
+
+        assert!(iter.next_timeout(Duration::from_millis(10)).is_none());
+        assert!(window
+            .next_timeout(Duration::from_millis(10))
+            .unwrap()
+            .is_empty());
+
+        thread::scope(|scope| {
+            scope.spawn(|| append_later(&db, 3));
+
+            let record = iter.next_timeout(Duration::from_secs(10)).unwrap();
+            assert_eq!(record.key.to_u128(), 1);
+
+            let mut received = 0;
+            while received < 3 {
+                received += window.next_timeout(Duration::from_secs(10)).unwrap().len();
+            }
+            assert_eq!(received, 3);
+        });
+
+        assert!(window.next().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_follow_stream() {
+        let _ = fs::remove_dir_all("test_follow_stream.db");
+        let db = StructDB::builder("test_follow_stream.db", Caches::default())
+            .with_struct::<MyTopic>()
+            .build()
+            .unwrap();
+
+        let mut topic = db.make_topic::<MyTopic>();
+        let first = topic.append(&vec![0; 32]).unwrap();
+
+        thread::scope(|scope| {
+            scope.spawn(|| append_later(&db, 5));
+
+            let received = futures::executor::block_on(async {
+                topic.stream().take(6).collect::<Vec<_>>().await
+            });
+            let keys: Vec<u128> = received.iter().map(|r| r.key.to_u128()).collect();
+            assert_eq!(keys, vec![1, 2, 3, 4, 5, 6]);
+
+            let received =
+                futures::executor::block_on(async { topic.stream_after(&first.key).next().await });
+            assert_eq!(received.unwrap().key.to_u128(), 2);
+        });
+    }
+}
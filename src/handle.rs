@@ -111,4 +111,39 @@ impl<P: VersionProvider> Migrations<P> {
             hash_map::Entry::Occupied(entry) => Err(Error::DuplicateMigration(*entry.key())),
         }
     }
+
+    /// Chains registered migrations from the stored version up to `target_version`.
+    ///
+    /// The version is persisted after every step, so an interrupted upgrade
+    /// continues from the last completed migration.
+    pub fn apply(&self, db: &StructDB) -> Result<Version, Error> {
+        let mut version = self
+            .version_provider
+            .get_version(db)?
+            .ok_or(Error::VersionNotFound)?;
+
+        while version != self.target_version {
+            if version > self.target_version {
+                return Err(Error::IncompatibleDbVersion {
+                    version,
+                    expected: self.target_version,
+                });
+            }
+
+            let migration = self
+                .migrations
+                .get(&version)
+                .ok_or(Error::MigrationNotFound(version))?;
+
+            let next = migration(db)?;
+            if next <= version {
+                return Err(Error::InvalidDbVersion);
+            }
+
+            self.version_provider.set_version(db, next)?;
+            version = next;
+        }
+
+        Ok(version)
+    }
 }
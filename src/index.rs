@@ -0,0 +1,434 @@
+use std::marker::PhantomData;
+
+use crate::{
+    batch::Batch,
+    errors::Result,
+    key_encoding::OrderedKey,
+    table::{Table, TableImpl},
+    typed_table::{TypedTable, TypedTableImpl},
+};
+
+/// Secondary index of a typed table, stored in its own column family.
+///
+/// Index keys are encoded with `OrderedKey`, so range scans follow the logical
+/// order of the index key. Entries of a non-unique index are the index key
+/// followed by the primary key, entries of a unique index are the index key alone.
+/// Both store the encoded primary key as value.
+pub trait Index: Table {
+    type Source: TypedTable;
+    type IndexKey: OrderedKey;
+
+    const UNIQUE: bool = false;
+
+    /// Extracts the index key from a value, `None` leaves the value out of the index.
+    fn index_key(value: &<Self::Source as TypedTable>::Value) -> Option<Self::IndexKey>;
+}
+
+/// Indexes of a typed table, registered from `TypedTable::indexes`.
+///
+/// Every handle of the table created through `StructDB` maintains them, in the
+/// shard of the handle.
+pub struct Indexes<T: TypedTable> {
+    writers: Vec<fn(&TableImpl<T>) -> Box<dyn IndexWriter<T>>>,
+}
+
+impl<T: TypedTable> Default for Indexes<T> {
+    fn default() -> Self {
+        Self { writers: vec![] }
+    }
+}
+
+impl<T: TypedTable> Indexes<T> {
+    pub fn add<I>(&mut self) -> &mut Self
+    where
+        I: Index<Source = T> + Send + Sync + 'static,
+    {
+        self.writers
+            .push(|table| Box::new(table.sibling::<I>()) as Box<dyn IndexWriter<T>>);
+        self
+    }
+
+    /// Opens the index tables next to `table`.
+    pub(crate) fn open(&self, table: &TableImpl<T>) -> Vec<Box<dyn IndexWriter<T>>> {
+        self.writers.iter().map(|open| open(table)).collect()
+    }
+}
+
+/// Keeps one index of a table in sync, entries are staged in the batch which
+/// writes the table row.
+pub(crate) trait IndexWriter<T: TypedTable>: Send + Sync {
+    fn stage(
+        &self,
+        batch: &mut Batch,
+        primary: &[u8],
+        old: Option<&T::Value>,
+        new: Option<&T::Value>,
+    ) -> Result<()>;
+}
+
+impl<I> IndexWriter<I::Source> for TableImpl<I>
+where
+    I: Index + Send + Sync,
+{
+    fn stage(
+        &self,
+        batch: &mut Batch,
+        primary: &[u8],
+        old: Option<&<I::Source as TypedTable>::Value>,
+        new: Option<&<I::Source as TypedTable>::Value>,
+    ) -> Result<()> {
+        let old = old.and_then(I::index_key).map(|key| key.to_ordered_bytes());
+        let new = new.and_then(I::index_key).map(|key| key.to_ordered_bytes());
+        if old == new {
+            return Ok(());
+        }
+
+        if let Some(old) = old {
+            batch.delete(self, entry_key::<I>(&old, primary));
+        }
+
+        if let Some(new) = new {
+            // NOTE: Stored owner is checked when the batch is written, while the entry is locked.
+            if I::UNIQUE {
+                batch.require_unique(self, &new, primary)?;
+            }
+            batch.put(self, entry_key::<I>(&new, primary), primary);
+        }
+
+        Ok(())
+    }
+}
+
+fn entry_key<I: Index>(index_key: &[u8], primary: &[u8]) -> Vec<u8> {
+    let mut entry = index_key.to_vec();
+    if !I::UNIQUE {
+        entry.extend_from_slice(primary);
+    }
+    entry
+}
+
+/// Iterator over the rows of a table in the order of an index.
+pub struct IndexIterator<'a, T, I>
+where
+    T: TypedTable,
+{
+    table: &'a TypedTableImpl<T>,
+    inner: rocksdb::DBIterator<'a>,
+    _ty: PhantomData<I>,
+}
+
+impl<'a, T, I> IndexIterator<'a, T, I>
+where
+    T: TypedTable,
+    I: Index<Source = T>,
+{
+    pub(crate) fn new(table: &'a TypedTableImpl<T>, inner: rocksdb::DBIterator<'a>) -> Self {
+        Self {
+            table,
+            inner,
+            _ty: Default::default(),
+        }
+    }
+}
+
+impl<'a, T, I> Iterator for IndexIterator<'a, T, I>
+where
+    T: TypedTable,
+    I: Index<Source = T>,
+{
+    type Item = Result<(T::Key, T::Value)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (entry, primary) = match self.inner.next()? {
+                Ok(item) => item,
+                Err(err) => return Some(Err(err.into())),
+            };
+
+            // NOTE: Entry may be newer or older than the row when a write races the scan, skip it.
+            let value = match self.table.table.get(&primary) {
+                Ok(Some(value)) => value,
+                Ok(None) => continue,
+                Err(err) => return Some(Err(err.into())),
+            };
+            let value = match T::decode_value(value.as_ref()) {
+                Ok(value) => value,
+                Err(err) => return Some(Err(err)),
+            };
+            let current =
+                I::index_key(&value).map(|key| entry_key::<I>(&key.to_ordered_bytes(), &primary));
+            if current.as_deref() != Some(entry.as_ref()) {
+                continue;
+            }
+
+            return Some(T::decode_key(&primary).map(|key| (key, value)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, thread};
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::{
+        builder::StructDB, caches::Caches, errors::Error, key_encoding::OrderedKey, table::Table,
+        typed_table::TypedTable,
+    };
+
+    use super::{Index, Indexes};
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    struct User {
+        email: String,
+        city: String,
+        age: u32,
+    }
+
+    struct Users;
+
+    impl Table for Users {
+        const NAME: &'static str = "users";
+    }
+
+    impl TypedTable for Users {
+        type Key = u64;
+        type Value = User;
+
+        fn indexes(indexes: &mut Indexes<Self>) {
+            indexes.add::<UsersByEmail>().add::<UsersByCityAge>();
+        }
+    }
+
+    struct UsersByEmail;
+
+    impl Table for UsersByEmail {
+        const NAME: &'static str = "users-by-email";
+    }
+
+    impl Index for UsersByEmail {
+        type Source = Users;
+        type IndexKey = String;
+
+        const UNIQUE: bool = true;
+
+        fn index_key(value: &User) -> Option<String> {
+            Some(value.email.clone())
+        }
+    }
+
+    struct UsersByCityAge;
+
+    impl Table for UsersByCityAge {
+        const NAME: &'static str = "users-by-city-age";
+    }
+
+    impl Index for UsersByCityAge {
+        type Source = Users;
+        type IndexKey = (String, u32);
+
+        fn index_key(value: &User) -> Option<(String, u32)> {
+            Some((value.city.clone(), value.age))
+        }
+    }
+
+    fn user(email: &str, city: &str, age: u32) -> User {
+        User {
+            email: email.to_string(),
+            city: city.to_string(),
+            age,
+        }
+    }
+
+    #[test]
+    fn test_index() {
+        let _ = fs::remove_dir_all("test_index.db");
+        let db = StructDB::builder("test_index.db", Caches::default())
+            .with_struct::<Users>()
+            .with_struct::<UsersByEmail>()
+            .with_struct::<UsersByCityAge>()
+            .build()
+            .unwrap();
+
+        let users = db.make_typed_table::<Users>();
+
+        users.insert(&1, &user("a@x", "berlin", 30)).unwrap();
+        users.insert(&2, &user("b@x", "berlin", 25)).unwrap();
+        users.insert(&3, &user("c@x", "paris", 40)).unwrap();
+
+        let found = users.lookup_by_index::<UsersByEmail>(&"b@x".to_string());
+        assert_eq!(found.unwrap()[0].0, 2);
+
+        let result = users.insert(&4, &user("a@x", "rome", 20));
+        assert!(matches!(result, Err(Error::UniqueIndexViolation(_))));
+        assert!(users.get(&4).unwrap().is_none());
+
+        let from = ("berlin".to_string(), 0);
+        let to = ("berlin".to_string(), 100);
+        let keys: Vec<u64> = users
+            .index_range::<UsersByCityAge>(Some(&from), Some(&to))
+            .map(|item| item.unwrap().0)
+            .collect();
+        assert_eq!(keys, vec![2, 1]);
+
+        // NOTE: Updating a row moves its entries, removing it drops them.
+        users.insert(&2, &user("b2@x", "paris", 25)).unwrap();
+        users.remove(&3).unwrap();
+        assert!(users
+            .lookup_by_index::<UsersByEmail>(&"b@x".to_string())
+            .unwrap()
+            .is_empty());
+        let paris = ("paris".to_string(), 0);
+        let keys: Vec<u64> = users
+            .index_range::<UsersByCityAge>(Some(&paris), None)
+            .map(|item| item.unwrap().0)
+            .collect();
+        assert_eq!(keys, vec![2]);
+
+        // NOTE: Every handle of the table maintains the registered indexes.
+        let other = db.make_typed_table::<Users>();
+        other.insert(&5, &user("e@x", "rome", 50)).unwrap();
+        let found = users.lookup_by_index::<UsersByEmail>(&"e@x".to_string());
+        assert_eq!(found.unwrap()[0].0, 5);
+
+        // NOTE: Entries which no longer match their row are skipped by lookups.
+        let raw = db.make_table::<Users>();
+        raw.insert(
+            Users::encode_key(&5).unwrap(),
+            Users::encode_value(&user("f@x", "rome", 50)).unwrap(),
+        )
+        .unwrap();
+        assert!(users
+            .lookup_by_index::<UsersByEmail>(&"e@x".to_string())
+            .unwrap()
+            .is_empty());
+
+        // NOTE: Concurrent updates of a row leave exactly one entry per index.
+        let users = &users;
+        thread::scope(|scope| {
+            for age in 60..64 {
+                scope.spawn(move || {
+                    for _ in 0..25 {
+                        users.insert(&6, &user("g@x", "oslo", age)).unwrap();
+                    }
+                });
+            }
+        });
+        let from = ("oslo".to_string(), 0);
+        let to = ("oslo".to_string(), 100);
+        let entries = db
+            .make_table::<UsersByCityAge>()
+            .iter_range(Some(&from.to_ordered_bytes()), Some(&to.to_ordered_bytes()))
+            .count();
+        assert_eq!(entries, 1);
+        let found = users.index_range::<UsersByCityAge>(Some(&from), Some(&to));
+        assert_eq!(found.count(), 1);
+    }
+
+    #[test]
+    fn test_index_unique_in_batch() {
+        let _ = fs::remove_dir_all("test_index_unique_in_batch.db");
+        let db = StructDB::builder("test_index_unique_in_batch.db", Caches::default())
+            .with_struct::<Users>()
+            .with_struct::<UsersByEmail>()
+            .with_struct::<UsersByCityAge>()
+            .build()
+            .unwrap();
+
+        let users = db.make_typed_table::<Users>();
+        users.insert(&1, &user("a@x", "berlin", 30)).unwrap();
+
+        let result = db.transaction(|txn| {
+            txn.put_typed(&users, &2, &user("b@x", "berlin", 25))?;
+            txn.put_typed(&users, &3, &user("b@x", "paris", 40))
+        });
+        assert!(matches!(result, Err(Error::UniqueIndexViolation(_))));
+        assert!(users.get(&2).unwrap().is_none());
+
+        // NOTE: A key given up earlier in the same transaction can be taken.
+        db.transaction(|txn| {
+            txn.put_typed(&users, &1, &user("a2@x", "berlin", 30))?;
+            txn.put_typed(&users, &2, &user("a@x", "berlin", 25))
+        })
+        .unwrap();
+        let found = users.lookup_by_index::<UsersByEmail>(&"a@x".to_string());
+        assert_eq!(found.unwrap()[0].0, 2);
+
+        // NOTE: Concurrent inserts of the same key are checked under the key lock.
+        let db = &db;
+        let inserted: usize = thread::scope(|scope| {
+            let handles: Vec<_> = (10..18)
+                .map(|key| {
+                    scope.spawn(move || {
+                        let users = db.make_typed_table::<Users>();
+                        users.insert(&key, &user("same@x", "oslo", 1)).is_ok() as usize
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).sum()
+        });
+        assert_eq!(inserted, 1);
+    }
+
+    #[test]
+    fn test_index_sharded() {
+        let _ = fs::remove_dir_all("test_index_sharded.db");
+        let db = StructDB::builder("test_index_sharded.db", Caches::default())
+            .with_struct::<Users>()
+            .with_struct::<UsersByEmail>()
+            .with_struct::<UsersByCityAge>()
+            .build()
+            .unwrap();
+
+        let eu = db.make_sharded_typed_table::<Users>(&"eu".to_string());
+        let us = db.make_sharded_typed_table::<Users>(&"us".to_string());
+        eu.insert(&1, &user("a@x", "berlin", 30)).unwrap();
+        us.insert(&1, &user("a@x", "boston", 30)).unwrap();
+
+        let found = us.lookup_by_index::<UsersByEmail>(&"a@x".to_string());
+        assert_eq!(found.unwrap()[0].1.city, "boston");
+        assert!(db
+            .make_table::<UsersByEmail>()
+            .iter_start()
+            .next()
+            .is_none());
+    }
+
+    #[test]
+    fn test_index_rebuild() {
+        let _ = fs::remove_dir_all("test_index_rebuild.db");
+        let db = StructDB::builder("test_index_rebuild.db", Caches::default())
+            .with_struct::<Users>()
+            .with_struct::<UsersByEmail>()
+            .with_struct::<UsersByCityAge>()
+            .build()
+            .unwrap();
+
+        // NOTE: Writes through the raw table bypass the indexes.
+        let users = db.make_typed_table::<Users>();
+        for (key, value) in [
+            (1, user("a@x", "berlin", 30)),
+            (2, user("b@x", "berlin", 25)),
+        ] {
+            let key = Users::encode_key(&key).unwrap();
+            let value = Users::encode_value(&value).unwrap();
+            users.table.insert(key, value).unwrap();
+        }
+        let key = ("berlin".to_string(), 30);
+        assert!(users
+            .lookup_by_index::<UsersByCityAge>(&key)
+            .unwrap()
+            .is_empty());
+
+        assert_eq!(users.rebuild_index::<UsersByCityAge>().unwrap(), 2);
+        let found = users.lookup_by_index::<UsersByCityAge>(&key).unwrap();
+        assert_eq!(found, vec![(1, user("a@x", "berlin", 30))]);
+
+        let key = Users::encode_key(&3).unwrap();
+        let value = Users::encode_value(&user("a@x", "rome", 20)).unwrap();
+        users.table.insert(key, value).unwrap();
+        let result = users.rebuild_index::<UsersByEmail>();
+        assert!(matches!(result, Err(Error::UniqueIndexViolation(_))));
+    }
+}
@@ -1,9 +1,11 @@
+use std::time::{Duration, Instant};
+
 use byte_counter::counter::ByteCounter;
 
 use crate::{
     record::SeqRecord,
     table::Table,
-    topic::{TopicImpl, TOPIC_ITERATOR_KEY_PREFIX, TOPIC_KEY_PREFIX},
+    topic::{TopicImpl, TOPIC_ITERATOR_KEY_PREFIX},
 };
 
 pub trait BatchIterator {
@@ -14,6 +16,16 @@ pub trait BatchIterator {
     fn tail_distance(&self) -> u128;
 }
 
+/// Defines when a named batch iterator advances its checkpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CommitMode {
+    /// Checkpoint is written by `next` before the batch is returned (at-most-once).
+    #[default]
+    Auto,
+    /// Checkpoint is written only by `commit` or `ack` (at-least-once).
+    Manual,
+}
+
 pub struct IteratorBatch<'a, T>
 where
     T: Table + 'a,
@@ -21,9 +33,13 @@ where
     pub topic: Box<&'a TopicImpl<T>>,
     //pub table: Box<&'a TableImpl<T>>,
     pub name: String,
+    pub commit_mode: CommitMode,
 
     _batch_size: usize,
     _state: rocksdb::DBRawIterator<'a>,
+    _delivered: Option<ByteCounter>,
+    _start: Option<Vec<u8>>,
+    _position: Option<Vec<u8>>,
 }
 
 impl<'a, T> IteratorBatch<'a, T>
@@ -31,40 +47,169 @@ where
     T: Table,
 {
     pub fn new(topic: Box<&'a TopicImpl<T>>, name: &str, batch_size: usize) -> Self {
-        let start_from = iter_checkpoint(name, &topic);
+        Self::with_commit_mode(topic, name, batch_size, CommitMode::Auto)
+    }
+
+    pub fn with_commit_mode(
+        topic: Box<&'a TopicImpl<T>>,
+        name: &str,
+        batch_size: usize,
+        commit_mode: CommitMode,
+    ) -> Self {
+        let checkpoint = iter_checkpoint(name, &topic).map(|key| key.to_string().into_bytes());
+        let _state = (*topic).iter_after(checkpoint.as_deref());
+
+        Self {
+            topic: topic,
+            name: name.to_string(),
+            commit_mode,
+            _batch_size: batch_size,
+            _state: _state,
+            _delivered: None,
+            _start: None,
+            _position: checkpoint,
+        }
+    }
 
-        let _state = topic.table.prefix_iterator(start_from);
+    /// Creates an iterator which ignores its checkpoint and starts at the record
+    /// with the given key, the checkpoint is overwritten by the next commit.
+    pub fn from_key(
+        topic: Box<&'a TopicImpl<T>>,
+        name: &str,
+        batch_size: usize,
+        commit_mode: CommitMode,
+        key: &ByteCounter,
+    ) -> Self {
+        let _start = topic.seek_key(key);
+        let _state = (*topic).iter_at(&_start);
 
         Self {
             topic: topic,
             name: name.to_string(),
+            commit_mode,
             _batch_size: batch_size,
             _state: _state,
+            _delivered: None,
+            _start: Some(_start),
+            _position: None,
         }
     }
+
+    /// Creates an iterator which ignores its checkpoint and starts at the first
+    /// record appended at or after `epoch_ns`, or after the last record if nothing
+    /// was appended since then.
+    pub fn from_time(
+        topic: Box<&'a TopicImpl<T>>,
+        name: &str,
+        batch_size: usize,
+        commit_mode: CommitMode,
+        epoch_ns: u128,
+    ) -> Self {
+        if let Some(first) = topic.seek_time(epoch_ns) {
+            return Self::from_key(topic, name, batch_size, commit_mode, &first);
+        }
+
+        let last = topic.last_insert().map(|key| key.to_string().into_bytes());
+        let _state = (*topic).iter_after(last.as_deref());
+
+        Self {
+            topic: topic,
+            name: name.to_string(),
+            commit_mode,
+            _batch_size: batch_size,
+            _state: _state,
+            _delivered: None,
+            _start: None,
+            _position: last,
+        }
+    }
+
+    /// Returns the next batch, waiting up to `timeout` for an append once the end
+    /// of the topic is reached.
+    pub fn next_timeout(&mut self, timeout: Duration) -> crate::errors::Result<Vec<SeqRecord>> {
+        let deadline = Instant::now() + timeout;
+        let topic: &'a TopicImpl<T> = *self.topic;
+
+        loop {
+            let seen = topic.notifier.version();
+            let batch = self.next()?;
+            if !batch.is_empty() {
+                return Ok(batch);
+            }
+
+            // NOTE: Iterator reads from an implicit snapshot, seek again to observe new appends.
+            self._state = match (&self._position, &self._start) {
+                (None, Some(start)) => topic.iter_at(start),
+                (position, _) => topic.iter_after(position.as_deref()),
+            };
+            let batch = self.next()?;
+            if !batch.is_empty() {
+                return Ok(batch);
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(batch);
+            }
+            topic.notifier.wait(seen, deadline - now);
+        }
+    }
+
+    /// Persists the given record key as the last processed record of this iterator.
+    pub fn commit(&mut self, last_key: &ByteCounter) -> crate::errors::Result<()> {
+        let key = iter_checkpoint_key(&self.name);
+        let value = self.topic.checkpoint_key(last_key);
+
+        self.topic.table.insert(key.as_bytes(), value.as_bytes())?;
+        Ok(())
+    }
+
+    /// Commits the last record handed out by `next`.
+    pub fn ack(&mut self) -> crate::errors::Result<()> {
+        match self._delivered.take() {
+            Some(last) => self.commit(&last),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Returns the key under which the checkpoint of a named iterator is stored.
+pub fn iter_checkpoint_key(name: &str) -> String {
+    format!("{}:{}", TOPIC_ITERATOR_KEY_PREFIX, name)
 }
 
-fn iter_checkpoint<T: Table>(name: &str, topic: &Box<&TopicImpl<T>>) -> String {
-    let last_iter = format!("{}:{}", TOPIC_ITERATOR_KEY_PREFIX, name);
+pub(crate) fn iter_checkpoint<T: Table>(name: &str, topic: &TopicImpl<T>) -> Option<ByteCounter> {
+    let last_iter = iter_checkpoint_key(name);
 
     let result = topic.table.get(last_iter.as_bytes());
-    let start_from = match result {
-        Ok(value) => match value {
-            Some(value) => {
-                let value = value.as_ref();
-                let value = String::from_utf8_lossy(value).to_string();
-                let from = ByteCounter::from(&value);
-                if from.valid {
-                    from.next_id().to_string()
-                } else {
-                    TOPIC_KEY_PREFIX.to_string()
-                }
-            }
-            None => TOPIC_KEY_PREFIX.to_string(),
-        },
-        Err(_) => TOPIC_KEY_PREFIX.to_string(),
+    match result {
+        Ok(Some(value)) => parse_checkpoint(value.as_ref()),
+        _ => None,
+    }
+}
+
+pub(crate) fn parse_checkpoint(value: &[u8]) -> Option<ByteCounter> {
+    let value = String::from_utf8_lossy(value).to_string();
+    let from = ByteCounter::from(&value);
+    if from.valid {
+        Some(from)
+    } else {
+        None
+    }
+}
+
+/// Number of records between the checkpoint of an iterator and the end of the topic.
+pub(crate) fn lag<T: Table>(topic: &TopicImpl<T>, checkpoint: Option<&ByteCounter>) -> u128 {
+    // NOTE: Marker is written with every append, `next_insert` of readers and other handles goes stale.
+    let last_insert = match topic.last_insert() {
+        Some(last) => last.next_id(),
+        None => topic.next_insert.clone(),
+    };
+    let checkpoint = match checkpoint {
+        Some(checkpoint) => checkpoint.next_id(),
+        None => ByteCounter::default().next_id(),
     };
-    start_from
+    last_insert.distance(&checkpoint)
 }
 
 impl<'a, T> BatchIterator for IteratorBatch<'a, T>
@@ -97,13 +242,11 @@ where
 
         match result.last() {
             Some(last) => {
-                let key = format!("{}:{}", TOPIC_ITERATOR_KEY_PREFIX, self.name);
-                let key_ser = key.as_bytes();
-
-                let value = last.key.to_string();
-                let value_ser = value.as_bytes();
-
-                let _ = self.topic.table.insert(key_ser, value_ser)?;
+                self._position = Some(last.key.to_string().into_bytes());
+                match self.commit_mode {
+                    CommitMode::Auto => self.commit(&last.key.clone())?,
+                    CommitMode::Manual => self._delivered = Some(last.key.clone()),
+                }
             }
             None => {}
         }
@@ -112,10 +255,7 @@ where
     }
 
     fn tail_distance(&self) -> u128 {
-        // TODO: Last insert should also be persisted so that when restarted, we continue inserting into the right place.
-        let last_insert = self.topic.next_insert.clone();
         let checkpoint = iter_checkpoint(self.name.as_ref(), &self.topic);
-        let checkpoint = ByteCounter::from(&checkpoint);
-        last_insert.distance(&checkpoint)
+        lag(&self.topic, checkpoint.as_ref())
     }
 }
@@ -0,0 +1,55 @@
+use byte_counter::counter::ByteCounter;
+
+use crate::{
+    errors::Result,
+    record::SeqRecord,
+    table::Table,
+    topic::{TopicImpl, TOPIC_KEY_END, TOPIC_KEY_PREFIX},
+};
+
+/// Iterator over the records of a topic in `[from, to)`, forward or in reverse.
+///
+/// Bounds are passed to RocksDB as iterate bounds, so the scan ends at the
+/// bound without reading keys which are not records. Read errors are yielded
+/// as items, the iteration ends after them.
+pub struct IteratorRange<'a> {
+    _state: rocksdb::DBIterator<'a>,
+}
+
+impl<'a> IteratorRange<'a> {
+    pub fn new<T: Table>(
+        topic: &'a TopicImpl<T>,
+        from: Option<&ByteCounter>,
+        to: Option<&ByteCounter>,
+        reverse: bool,
+    ) -> Self {
+        let from = match from {
+            Some(from) => topic.seek_key(from),
+            None => TOPIC_KEY_PREFIX.as_bytes().to_vec(),
+        };
+        let to = match to {
+            Some(to) => topic.seek_key(to),
+            None => TOPIC_KEY_END.as_bytes().to_vec(),
+        };
+
+        let _state = if reverse {
+            topic.table.iter_range_rev(Some(&from), Some(&to))
+        } else {
+            topic.table.iter_range(Some(&from), Some(&to))
+        };
+
+        Self { _state }
+    }
+}
+
+impl<'a> Iterator for IteratorRange<'a> {
+    type Item = Result<SeqRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, value) = match self._state.next()? {
+            Ok(item) => item,
+            Err(err) => return Some(Err(err.into())),
+        };
+        Some(Ok(SeqRecord::from(Some((key.as_ref(), value.as_ref())))))
+    }
+}
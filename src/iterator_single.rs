@@ -1,8 +1,8 @@
-use crate::{
-    record::SeqRecord,
-    table::Table,
-    topic::{TopicImpl, TOPIC_KEY_PREFIX},
-};
+use std::time::{Duration, Instant};
+
+use byte_counter::counter::ByteCounter;
+
+use crate::{record::SeqRecord, table::Table, topic::TopicImpl};
 
 pub struct IteratorSingle<'a, T>
 where
@@ -10,6 +10,8 @@ where
 {
     pub topic: Box<&'a TopicImpl<T>>,
     _state: rocksdb::DBRawIterator<'a>,
+    _start: Option<Vec<u8>>,
+    _last: Option<Vec<u8>>,
 }
 
 impl<'a, T> IteratorSingle<'a, T>
@@ -17,11 +19,71 @@ where
     T: Table,
 {
     pub fn new(topic: Box<&'a TopicImpl<T>>) -> Self {
-        let _state = topic.table.prefix_iterator(TOPIC_KEY_PREFIX);
+        Self::after(topic, None)
+    }
+
+    /// Creates an iterator which starts at the record with the given key.
+    pub fn from_key(topic: Box<&'a TopicImpl<T>>, key: &ByteCounter) -> Self {
+        let _start = topic.seek_key(key);
+        let _state = (*topic).iter_at(&_start);
+
+        Self {
+            topic: topic,
+            _state: _state,
+            _start: Some(_start),
+            _last: None,
+        }
+    }
+
+    /// Creates an iterator which starts at the first record appended at or after
+    /// `epoch_ns`, or after the last record if nothing was appended since then.
+    pub fn from_time(topic: Box<&'a TopicImpl<T>>, epoch_ns: u128) -> Self {
+        match topic.seek_time(epoch_ns) {
+            Some(first) => Self::from_key(topic, &first),
+            None => {
+                let last = topic.last_insert().map(|key| key.to_string().into_bytes());
+                Self::after(topic, last)
+            }
+        }
+    }
+
+    fn after(topic: Box<&'a TopicImpl<T>>, last: Option<Vec<u8>>) -> Self {
+        let _state = (*topic).iter_after(last.as_deref());
 
         Self {
             topic: topic,
             _state: _state,
+            _start: None,
+            _last: last,
+        }
+    }
+
+    /// Returns the next record, waiting up to `timeout` for an append once the end
+    /// of the topic is reached.
+    pub fn next_timeout(&mut self, timeout: Duration) -> Option<SeqRecord> {
+        let deadline = Instant::now() + timeout;
+        let topic: &'a TopicImpl<T> = *self.topic;
+
+        loop {
+            let seen = topic.notifier.version();
+            if let Some(record) = self.next() {
+                return Some(record);
+            }
+
+            // NOTE: Iterator reads from an implicit snapshot, seek again to observe new appends.
+            self._state = match (&self._last, &self._start) {
+                (None, Some(start)) => topic.iter_at(start),
+                (last, _) => topic.iter_after(last.as_deref()),
+            };
+            if let Some(record) = self.next() {
+                return Some(record);
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            topic.notifier.wait(seen, deadline - now);
         }
     }
 }
@@ -43,6 +105,7 @@ where
             return None;
         }
 
+        self._last = self._state.key().map(|key| key.to_vec());
         self._state.next();
         Some(record)
     }
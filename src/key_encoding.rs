@@ -0,0 +1,253 @@
+use crate::errors::{Error, Result};
+
+/// Escapes a zero byte inside of variable length values.
+const ESCAPE: u8 = 0xff;
+/// Terminates variable length values, sorts before any escaped content.
+const TERMINATOR: u8 = 0x01;
+
+/// Key encoding whose byte order matches the logical order of the values.
+///
+/// Integers are stored big-endian with the sign bit flipped, strings and byte
+/// vectors are escaped and terminated, tuples concatenate their elements. The
+/// encoded bytes can be compared by RocksDB's default bytewise comparator.
+pub trait OrderedKey: Sized {
+    fn encode_ordered(&self, out: &mut Vec<u8>);
+
+    fn decode_ordered(input: &mut &[u8]) -> Result<Self>;
+
+    fn to_ordered_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_ordered(&mut out);
+        out
+    }
+
+    fn from_ordered_bytes(encoded: &[u8]) -> Result<Self> {
+        let mut input = encoded;
+        let value = Self::decode_ordered(&mut input)?;
+        if !input.is_empty() {
+            return Err(Error::DeserializationFailed(format!(
+                "{} trailing bytes after ordered key",
+                input.len()
+            )));
+        }
+        Ok(value)
+    }
+}
+
+/// Encodes the key with `OrderedKey`, usable as `TypedTable::encode_key`.
+pub fn encode<K: OrderedKey>(key: &K) -> Result<Vec<u8>> {
+    Ok(key.to_ordered_bytes())
+}
+
+/// Decodes the key with `OrderedKey`, usable as `TypedTable::decode_key`.
+pub fn decode<K: OrderedKey>(encoded: &[u8]) -> Result<K> {
+    K::from_ordered_bytes(encoded)
+}
+
+/// Smallest key which is greater than all keys starting with `prefix`.
+pub(crate) fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if input.len() < len {
+        return Err(Error::DeserializationFailed(format!(
+            "ordered key too short, expected {} more bytes",
+            len - input.len()
+        )));
+    }
+    let (head, tail) = input.split_at(len);
+    *input = tail;
+    Ok(head)
+}
+
+macro_rules! ordered_unsigned {
+    ($($ty:ty),*) => {
+        $(
+            impl OrderedKey for $ty {
+                fn encode_ordered(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_be_bytes());
+                }
+
+                fn decode_ordered(input: &mut &[u8]) -> Result<Self> {
+                    let bytes = take(input, std::mem::size_of::<$ty>())?;
+                    Ok(<$ty>::from_be_bytes(bytes.try_into().unwrap()))
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! ordered_signed {
+    ($($ty:ty => $unsigned:ty),*) => {
+        $(
+            impl OrderedKey for $ty {
+                fn encode_ordered(&self, out: &mut Vec<u8>) {
+                    // NOTE: Flipping the sign bit moves negative values before positive ones.
+                    let flipped = (*self as $unsigned) ^ (1 << (<$unsigned>::BITS - 1));
+                    out.extend_from_slice(&flipped.to_be_bytes());
+                }
+
+                fn decode_ordered(input: &mut &[u8]) -> Result<Self> {
+                    let flipped = <$unsigned>::decode_ordered(input)?;
+                    Ok((flipped ^ (1 << (<$unsigned>::BITS - 1))) as $ty)
+                }
+            }
+        )*
+    };
+}
+
+ordered_unsigned!(u8, u16, u32, u64, u128);
+ordered_signed!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128);
+
+impl OrderedKey for bool {
+    fn encode_ordered(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+
+    fn decode_ordered(input: &mut &[u8]) -> Result<Self> {
+        match u8::decode_ordered(input)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            other => Err(Error::DeserializationFailed(format!(
+                "invalid ordered bool: {}",
+                other
+            ))),
+        }
+    }
+}
+
+impl OrderedKey for Vec<u8> {
+    fn encode_ordered(&self, out: &mut Vec<u8>) {
+        for byte in self {
+            out.push(*byte);
+            if *byte == 0 {
+                out.push(ESCAPE);
+            }
+        }
+        out.push(0);
+        out.push(TERMINATOR);
+    }
+
+    fn decode_ordered(input: &mut &[u8]) -> Result<Self> {
+        let mut result = Vec::new();
+        loop {
+            let byte = take(input, 1)?[0];
+            if byte != 0 {
+                result.push(byte);
+                continue;
+            }
+
+            match take(input, 1)?[0] {
+                ESCAPE => result.push(0),
+                TERMINATOR => return Ok(result),
+                other => {
+                    return Err(Error::DeserializationFailed(format!(
+                        "invalid ordered escape: {}",
+                        other
+                    )))
+                }
+            }
+        }
+    }
+}
+
+impl OrderedKey for String {
+    fn encode_ordered(&self, out: &mut Vec<u8>) {
+        // NOTE: UTF-8 byte order matches code point order.
+        self.as_bytes().to_vec().encode_ordered(out);
+    }
+
+    fn decode_ordered(input: &mut &[u8]) -> Result<Self> {
+        let bytes = Vec::<u8>::decode_ordered(input)?;
+        String::from_utf8(bytes).map_err(|err| Error::DeserializationFailed(err.to_string()))
+    }
+}
+
+macro_rules! ordered_tuple {
+    ($($name:ident $var:ident),+) => {
+        impl<$($name: OrderedKey),+> OrderedKey for ($($name,)+) {
+            fn encode_ordered(&self, out: &mut Vec<u8>) {
+                let ($($var,)+) = self;
+                $($var.encode_ordered(out);)+
+            }
+
+            fn decode_ordered(input: &mut &[u8]) -> Result<Self> {
+                Ok(($($name::decode_ordered(input)?,)+))
+            }
+        }
+    };
+}
+
+ordered_tuple!(A a);
+ordered_tuple!(A a, B b);
+ordered_tuple!(A a, B b, C c);
+ordered_tuple!(A a, B b, C c, D d);
+ordered_tuple!(A a, B b, C c, D d, E e);
+
+#[cfg(test)]
+mod tests {
+    use super::OrderedKey;
+
+    fn assert_ordered<K: OrderedKey + Ord + Clone + std::fmt::Debug>(mut values: Vec<K>) {
+        values.sort();
+        let encoded: Vec<Vec<u8>> = values.iter().map(|v| v.to_ordered_bytes()).collect();
+
+        let mut sorted = encoded.clone();
+        sorted.sort();
+        assert_eq!(encoded, sorted);
+
+        for (value, bytes) in values.iter().zip(encoded.iter()) {
+            assert_eq!(&K::from_ordered_bytes(bytes).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_ordered_integers() {
+        assert_ordered(vec![0u64, 1, 255, 256, 65_535, u64::MAX]);
+        assert_ordered(vec![i64::MIN, -256, -1, 0, 1, 255, i64::MAX]);
+        assert_ordered(vec![i8::MIN, -1, 0, 1, i8::MAX]);
+        assert_ordered(vec![i128::MIN, -1, 0, 1, i128::MAX]);
+    }
+
+    #[test]
+    fn test_ordered_strings() {
+        assert_ordered(vec![
+            String::new(),
+            "\0".to_string(),
+            "\0\0".to_string(),
+            "a".to_string(),
+            "a\0".to_string(),
+            "a\0b".to_string(),
+            "ab".to_string(),
+            "b".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_ordered_tuples() {
+        assert_ordered(vec![
+            (-1i32, "z".to_string()),
+            (0, String::new()),
+            (0, "a".to_string()),
+            (0, "ab".to_string()),
+            (1, String::new()),
+        ]);
+        assert_ordered(vec![(1u8, 2u16, -3i64), (1, 2, 3), (1, 3, i64::MIN)]);
+    }
+
+    #[test]
+    fn test_ordered_decode_errors() {
+        assert!(u32::from_ordered_bytes(&[0, 1]).is_err());
+        assert!(u8::from_ordered_bytes(&[0, 1]).is_err());
+        assert!(String::from_ordered_bytes(&[b'a', 0]).is_err());
+        assert!(String::from_ordered_bytes(&[b'a', 0, 7]).is_err());
+    }
+}
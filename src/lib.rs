@@ -1,18 +1,34 @@
 extern crate librocksdb_sys;
 extern crate thiserror;
 
+pub mod backup;
+pub mod batch;
 pub mod builder;
 pub mod caches;
+pub mod changelog;
+pub mod consumers;
 pub mod database;
 pub mod errors;
+pub mod expiring;
+pub mod follow;
 pub mod handle;
+pub mod index;
 pub mod iterator_batch;
+pub mod iterator_range;
 pub mod iterator_single;
+pub mod key_encoding;
+pub mod locks;
+pub mod merge;
 pub mod record;
+pub mod retention;
 pub mod serialization;
+pub mod shared_topic;
 pub mod snapshot;
 pub mod stats;
 pub mod table;
 pub mod timestamp;
 pub mod topic;
+pub mod transaction;
+pub mod typed_table;
+pub mod watch;
 pub mod writer;
@@ -0,0 +1,183 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Condvar, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+/// Number of mutexes keys are spread over.
+const LOCK_STRIPES: usize = 64;
+
+/// Number of write versions keys are spread over, more than stripes so that
+/// unrelated keys rarely make a transaction conflict.
+const VERSION_SLOTS: usize = 1024;
+
+/// Striped per-key locks which serialize conditional writes of a database.
+///
+/// Keys are hashed onto a fixed set of mutexes, unrelated keys may share a
+/// stripe and wait for each other, but writes of the same key never overlap.
+///
+/// Every locked write also records a version for its key, which transactions
+/// use to detect writes since they started. Transactions lock the keys they
+/// use in a separate table of row locks which plain writes never wait for.
+pub struct KeyLocks {
+    stripes: Vec<Mutex<()>>,
+    clock: AtomicU64,
+    versions: Vec<AtomicU64>,
+    owners: AtomicU64,
+    rows: Mutex<HashMap<(String, Vec<u8>), u64>>,
+    released: Condvar,
+}
+
+impl Default for KeyLocks {
+    fn default() -> Self {
+        Self {
+            stripes: (0..LOCK_STRIPES).map(|_| Mutex::new(())).collect(),
+            clock: AtomicU64::new(0),
+            versions: (0..VERSION_SLOTS).map(|_| AtomicU64::new(0)).collect(),
+            owners: AtomicU64::new(0),
+            rows: Mutex::new(HashMap::new()),
+            released: Condvar::new(),
+        }
+    }
+}
+
+impl KeyLocks {
+    /// Locks the key of the column family until the guard is dropped.
+    pub fn lock(&self, cf_name: &str, key: &[u8]) -> MutexGuard<'_, ()> {
+        self.lock_stripe(self.stripe(cf_name, key))
+    }
+
+    /// Locks all given keys, stripes are taken in ascending order so callers
+    /// locking several keys never wait for each other in a cycle.
+    pub fn lock_all<'k, I>(&self, keys: I) -> Vec<MutexGuard<'_, ()>>
+    where
+        I: IntoIterator<Item = (&'k str, &'k [u8])>,
+    {
+        let mut stripes: Vec<usize> = keys
+            .into_iter()
+            .map(|(cf_name, key)| self.stripe(cf_name, key))
+            .collect();
+        stripes.sort_unstable();
+        stripes.dedup();
+
+        stripes
+            .into_iter()
+            .map(|stripe| self.lock_stripe(stripe))
+            .collect()
+    }
+
+    /// Locks every key of the database, used by writes whose keys are not known upfront.
+    pub fn lock_every(&self) -> Vec<MutexGuard<'_, ()>> {
+        (0..self.stripes.len())
+            .map(|stripe| self.lock_stripe(stripe))
+            .collect()
+    }
+
+    /// Current write version, a transaction started now sees all writes up to it.
+    pub(crate) fn version(&self) -> u64 {
+        self.clock.load(Ordering::SeqCst)
+    }
+
+    /// Records a write of the key, called after writing while the key is locked.
+    pub(crate) fn record_write(&self, cf_name: &str, key: &[u8]) {
+        let version = self.clock.fetch_add(1, Ordering::SeqCst) + 1;
+        self.versions[self.slot(cf_name, key)].store(version, Ordering::SeqCst);
+    }
+
+    /// Records a write of every key, used by writes whose keys are not known upfront.
+    pub(crate) fn record_write_all(&self) {
+        let version = self.clock.fetch_add(1, Ordering::SeqCst) + 1;
+        for slot in &self.versions {
+            slot.store(version, Ordering::SeqCst);
+        }
+    }
+
+    /// Whether the key may have been written after `version`, exact while the key is locked.
+    pub(crate) fn written_since(&self, cf_name: &str, key: &[u8], version: u64) -> bool {
+        self.versions[self.slot(cf_name, key)].load(Ordering::SeqCst) > version
+    }
+
+    /// New owner of row locks, one per transaction.
+    pub(crate) fn new_owner(&self) -> u64 {
+        self.owners.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Locks the row for `owner` unless another owner keeps it for `timeout`.
+    ///
+    /// Row locks are re-entrant, an owner locking a row it holds already
+    /// succeeds right away. Returns whether the row is locked.
+    pub(crate) fn lock_row(
+        &self,
+        owner: u64,
+        cf_name: &str,
+        key: &[u8],
+        timeout: Duration,
+    ) -> bool {
+        let deadline = Instant::now() + timeout;
+        let row = (cf_name.to_string(), key.to_vec());
+
+        let mut rows = self.lock_rows();
+        loop {
+            match rows.get(&row) {
+                Some(holder) if *holder != owner => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return false;
+                    }
+                    rows = self
+                        .released
+                        .wait_timeout(rows, deadline - now)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .0;
+                }
+                _ => {
+                    rows.insert(row, owner);
+                    return true;
+                }
+            }
+        }
+    }
+
+    /// Releases all rows locked by `owner`.
+    pub(crate) fn unlock_rows(&self, owner: u64) {
+        let mut rows = self.lock_rows();
+        let before = rows.len();
+        rows.retain(|_, holder| *holder != owner);
+        if rows.len() != before {
+            self.released.notify_all();
+        }
+    }
+
+    fn stripe(&self, cf_name: &str, key: &[u8]) -> usize {
+        hash_key(cf_name, key) as usize % self.stripes.len()
+    }
+
+    fn slot(&self, cf_name: &str, key: &[u8]) -> usize {
+        hash_key(cf_name, key) as usize % self.versions.len()
+    }
+
+    fn lock_stripe(&self, stripe: usize) -> MutexGuard<'_, ()> {
+        // NOTE: Guarded data is empty, a panicked writer leaves nothing inconsistent.
+        self.stripes[stripe]
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn lock_rows(&self) -> MutexGuard<'_, HashMap<(String, Vec<u8>), u64>> {
+        // NOTE: Rows are only inserted and removed whole, a panicked owner leaves them consistent.
+        self.rows
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn hash_key(cf_name: &str, key: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    cf_name.hash(&mut hasher);
+    key.hash(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,334 @@
+use std::collections::BTreeSet;
+
+use rocksdb::MergeOperands;
+
+use crate::{
+    errors::{Error, Result},
+    table::{Table, TableImpl},
+};
+
+/// Merge operators for values which are combined by RocksDB instead of read-modify-write.
+///
+/// The operator is registered per column family from `Table::options`:
+///
+/// ```ignore
+/// impl Table for PageViews {
+///     const NAME: &'static str = "page-views";
+///
+///     fn options(opts: &mut rocksdb::Options, _caches: &Caches) {
+///         Aggregate::Counter.register(opts);
+///     }
+/// }
+/// ```
+///
+/// Column families opened with `Builder::build_all` use default options, tables
+/// with an aggregate have to be registered with `with_struct`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    /// Sum of `i64` deltas, see `CounterTable`.
+    Counter,
+    /// Largest `i64` written, see `RegisterTable`.
+    Max,
+    /// Smallest `i64` written, see `RegisterTable`.
+    Min,
+    /// Union of byte string members, see `SetTable`.
+    Set,
+}
+
+impl Aggregate {
+    pub fn register(self, opts: &mut rocksdb::Options) {
+        match self {
+            Aggregate::Counter => {
+                opts.set_merge_operator("structdb.counter", counter_merge, keep_operands)
+            }
+            Aggregate::Max => opts.set_merge_operator_associative("structdb.max", max_merge),
+            Aggregate::Min => opts.set_merge_operator_associative("structdb.min", min_merge),
+            Aggregate::Set => opts.set_merge_operator_associative("structdb.set", set_merge),
+        }
+    }
+}
+
+fn decode_i64(value: &[u8]) -> Option<i64> {
+    Some(i64::from_le_bytes(value.try_into().ok()?))
+}
+
+fn fold_i64<F>(existing: Option<&[u8]>, operands: &MergeOperands, f: F) -> Option<Vec<u8>>
+where
+    F: Fn(i64, i64) -> i64,
+{
+    let mut result = match existing {
+        Some(existing) => Some(decode_i64(existing)?),
+        None => None,
+    };
+    for operand in operands {
+        let operand = decode_i64(operand)?;
+        result = Some(match result {
+            Some(result) => f(result, operand),
+            None => operand,
+        });
+    }
+    result.map(|result| result.to_le_bytes().to_vec())
+}
+
+fn counter_merge(
+    _key: &[u8],
+    existing: Option<&[u8]>,
+    operands: &MergeOperands,
+) -> Option<Vec<u8>> {
+    fold_i64(existing, operands, i64::saturating_add)
+}
+
+/// Partial merge which combines nothing, saturating additions are not associative
+/// and are only applied in order onto the stored value.
+fn keep_operands(
+    _key: &[u8],
+    _existing: Option<&[u8]>,
+    _operands: &MergeOperands,
+) -> Option<Vec<u8>> {
+    None
+}
+
+fn max_merge(_key: &[u8], existing: Option<&[u8]>, operands: &MergeOperands) -> Option<Vec<u8>> {
+    fold_i64(existing, operands, i64::max)
+}
+
+fn min_merge(_key: &[u8], existing: Option<&[u8]>, operands: &MergeOperands) -> Option<Vec<u8>> {
+    fold_i64(existing, operands, i64::min)
+}
+
+/// Members are stored sorted, each prefixed with its length as big-endian `u32`.
+fn encode_set(members: &BTreeSet<Vec<u8>>) -> Vec<u8> {
+    let mut out = Vec::new();
+    for member in members {
+        out.extend_from_slice(&(member.len() as u32).to_be_bytes());
+        out.extend_from_slice(member);
+    }
+    out
+}
+
+fn decode_set_into(mut input: &[u8], members: &mut BTreeSet<Vec<u8>>) -> Option<()> {
+    while !input.is_empty() {
+        let len = u32::from_be_bytes(input.get(..4)?.try_into().ok()?) as usize;
+        let member = input.get(4..4 + len)?;
+        members.insert(member.to_vec());
+        input = &input[4 + len..];
+    }
+    Some(())
+}
+
+fn set_merge(_key: &[u8], existing: Option<&[u8]>, operands: &MergeOperands) -> Option<Vec<u8>> {
+    let mut members = BTreeSet::new();
+    if let Some(existing) = existing {
+        decode_set_into(existing, &mut members)?;
+    }
+    for operand in operands {
+        decode_set_into(operand, &mut members)?;
+    }
+    Some(encode_set(&members))
+}
+
+fn invalid_value(table: &str) -> Error {
+    Error::DeserializationFailed(format!("invalid aggregate value in {}", table))
+}
+
+/// Atomic `i64` counters of a table with `Aggregate::Counter`.
+pub struct CounterTable<T> {
+    pub table: TableImpl<T>,
+}
+
+impl<T> CounterTable<T>
+where
+    T: Table,
+{
+    pub fn new(table: TableImpl<T>) -> Self {
+        Self { table }
+    }
+
+    /// Adds `delta` to the counter, negative values decrement it.
+    ///
+    /// The counter saturates at `i64::MAX` and `i64::MIN` instead of wrapping around.
+    pub fn add<K: AsRef<[u8]>>(&self, key: K, delta: i64) -> Result<()> {
+        self.table
+            .merge(key, delta.to_le_bytes())
+            .map_err(Into::into)
+    }
+
+    /// Current value of the counter, missing counters are zero.
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<i64> {
+        match self.table.get(key)? {
+            Some(value) => decode_i64(&value).ok_or_else(|| invalid_value(&self.table.name)),
+            None => Ok(0),
+        }
+    }
+
+    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Result<()> {
+        self.table.remove(key).map_err(Into::into)
+    }
+}
+
+/// `i64` registers of a table with `Aggregate::Max` or `Aggregate::Min`,
+/// which keep the largest or smallest value written.
+pub struct RegisterTable<T> {
+    pub table: TableImpl<T>,
+}
+
+impl<T> RegisterTable<T>
+where
+    T: Table,
+{
+    pub fn new(table: TableImpl<T>) -> Self {
+        Self { table }
+    }
+
+    pub fn update<K: AsRef<[u8]>>(&self, key: K, value: i64) -> Result<()> {
+        self.table
+            .merge(key, value.to_le_bytes())
+            .map_err(Into::into)
+    }
+
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<i64>> {
+        match self.table.get(key)? {
+            Some(value) => decode_i64(&value)
+                .map(Some)
+                .ok_or_else(|| invalid_value(&self.table.name)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Result<()> {
+        self.table.remove(key).map_err(Into::into)
+    }
+}
+
+/// Sets of byte strings of a table with `Aggregate::Set`, members are only added.
+pub struct SetTable<T> {
+    pub table: TableImpl<T>,
+}
+
+impl<T> SetTable<T>
+where
+    T: Table,
+{
+    pub fn new(table: TableImpl<T>) -> Self {
+        Self { table }
+    }
+
+    pub fn add<K, M>(&self, key: K, member: M) -> Result<()>
+    where
+        K: AsRef<[u8]>,
+        M: AsRef<[u8]>,
+    {
+        let members = BTreeSet::from([member.as_ref().to_vec()]);
+        self.table
+            .merge(key, encode_set(&members))
+            .map_err(Into::into)
+    }
+
+    /// Members of the set in byte order, missing sets are empty.
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<BTreeSet<Vec<u8>>> {
+        let mut members = BTreeSet::new();
+        if let Some(value) = self.table.get(key)? {
+            decode_set_into(&value, &mut members).ok_or_else(|| invalid_value(&self.table.name))?;
+        }
+        Ok(members)
+    }
+
+    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Result<()> {
+        self.table.remove(key).map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, fs, thread};
+
+    use crate::{builder::StructDB, caches::Caches, table::Table};
+
+    use super::{Aggregate, CounterTable, RegisterTable, SetTable};
+
+    struct PageViews;
+
+    impl Table for PageViews {
+        const NAME: &'static str = "page-views";
+
+        fn options(opts: &mut rocksdb::Options, _caches: &Caches) {
+            Aggregate::Counter.register(opts);
+        }
+    }
+
+    struct Highscores;
+
+    impl Table for Highscores {
+        const NAME: &'static str = "highscores";
+
+        fn options(opts: &mut rocksdb::Options, _caches: &Caches) {
+            Aggregate::Max.register(opts);
+        }
+    }
+
+    struct Tags;
+
+    impl Table for Tags {
+        const NAME: &'static str = "tags";
+
+        fn options(opts: &mut rocksdb::Options, _caches: &Caches) {
+            Aggregate::Set.register(opts);
+        }
+    }
+
+    #[test]
+    fn test_aggregates() {
+        let _ = fs::remove_dir_all("test_aggregates.db");
+        let db = StructDB::builder("test_aggregates.db", Caches::default())
+            .with_struct::<PageViews>()
+            .with_struct::<Highscores>()
+            .with_struct::<Tags>()
+            .build()
+            .unwrap();
+
+        let db = &db;
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(move || {
+                    let views = CounterTable::new(db.make_table::<PageViews>());
+                    for _ in 0..100 {
+                        views.add("/index", 1).unwrap();
+                    }
+                });
+            }
+        });
+
+        let views = CounterTable::new(db.make_table::<PageViews>());
+        views.add("/index", -10).unwrap();
+        assert_eq!(views.get("/index").unwrap(), 390);
+        assert_eq!(views.get("/missing").unwrap(), 0);
+
+        views.add("/hot", i64::MAX).unwrap();
+        views.add("/hot", 1).unwrap();
+        assert_eq!(views.get("/hot").unwrap(), i64::MAX);
+
+        // NOTE: Deltas apply in order, a compaction must not combine `1` and `-1` first.
+        views.add("/hot", -1).unwrap();
+        views.table.compact_range(None, None);
+        assert_eq!(views.get("/hot").unwrap(), i64::MAX - 1);
+
+        let scores = RegisterTable::new(db.make_table::<Highscores>());
+        assert_eq!(scores.get("alice").unwrap(), None);
+        for score in [10, 42, 7, -3] {
+            scores.update("alice", score).unwrap();
+        }
+        assert_eq!(scores.get("alice").unwrap(), Some(42));
+
+        let tags = SetTable::new(db.make_table::<Tags>());
+        for tag in ["rust", "db", "rust"] {
+            tags.add("post-1", tag).unwrap();
+        }
+        let expected = BTreeSet::from([b"db".to_vec(), b"rust".to_vec()]);
+        assert_eq!(tags.get("post-1").unwrap(), expected);
+
+        // NOTE: Removing drops the merged value, later operands start from scratch.
+        views.remove("/index").unwrap();
+        views.add("/index", 2).unwrap();
+        assert_eq!(views.get("/index").unwrap(), 2);
+    }
+}
@@ -0,0 +1,361 @@
+use std::{
+    sync::mpsc::{self, RecvTimeoutError},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use byte_counter::counter::ByteCounter;
+
+use crate::{
+    errors::Result,
+    record::SeqRecord,
+    table::TableImpl,
+    timestamp::epoch_secs,
+    topic::{
+        Topic, TOPIC_ITERATOR_KEY_PREFIX, TOPIC_KEY_END, TOPIC_KEY_PREFIX,
+        TOPIC_TIME_INDEX_KEY_PREFIX,
+    },
+};
+
+/// Retention policy of a topic, limits which are `None` are not enforced.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Retention {
+    pub max_records: Option<u128>,
+    pub max_bytes: Option<u128>,
+    pub max_age: Option<Duration>,
+    /// Delete records even if named iterators have not committed them yet.
+    pub force: bool,
+}
+
+impl Retention {
+    pub fn is_unbounded(&self) -> bool {
+        self.max_records.is_none() && self.max_bytes.is_none() && self.max_age.is_none()
+    }
+}
+
+/// Deletes the oldest records of a topic which are outside of the retention policy.
+///
+/// Records are walked from the newest one until a limit is exceeded, everything
+/// up to and including that record is removed with a single range deletion.
+/// Returns the key of the last removed record, `None` if nothing was removed.
+pub fn enforce<T: Topic>(
+    table: &TableImpl<T>,
+    retention: &Retention,
+) -> Result<Option<ByteCounter>> {
+    if retention.is_unbounded() {
+        return Ok(None);
+    }
+
+    let now = epoch_secs();
+    let mut count: u128 = 0;
+    let mut bytes: u128 = 0;
+    let mut cutoff: Option<Vec<u8>> = None;
+
+    let mut iter = table.raw_iterator();
+    iter.seek_for_prev(TOPIC_KEY_END);
+    loop {
+        if !iter.valid() {
+            break;
+        }
+        let item = iter.item();
+        if item.is_none() {
+            break;
+        }
+
+        let record = SeqRecord::from(item);
+        if !record.is_valid() {
+            break;
+        }
+
+        count += 1;
+        bytes += record.size() as u128;
+
+        let over_records = retention.max_records.map_or(false, |max| count > max);
+        let over_bytes = retention.max_bytes.map_or(false, |max| bytes > max);
+        let expired = retention.max_age.map_or(false, |max| {
+            now.saturating_sub(record.key.timestamp.value()) > max.as_secs()
+        });
+
+        if over_records || over_bytes || expired {
+            cutoff = iter.key().map(|key| key.to_vec());
+            break;
+        }
+
+        iter.prev();
+    }
+
+    let mut cutoff = match cutoff {
+        Some(cutoff) => cutoff,
+        None => return Ok(None),
+    };
+
+    if !retention.force {
+        match min_checkpoint(table) {
+            Some(checkpoint) if checkpoint < cutoff => cutoff = checkpoint,
+            _ => {}
+        }
+    }
+
+    // NOTE: Checkpoint may lag behind records removed earlier, nothing is left to delete then.
+    let mut first = table.iter_range(
+        Some(TOPIC_KEY_PREFIX.as_bytes()),
+        Some(TOPIC_KEY_END.as_bytes()),
+    );
+    match first.next().transpose()? {
+        Some((first, _)) if first.as_ref() <= cutoff.as_slice() => {}
+        _ => return Ok(None),
+    }
+
+    let last = ByteCounter::from(&String::from_utf8_lossy(&cutoff).to_string());
+    prune_time_index(table, &cutoff)?;
+
+    // NOTE: Range end is exclusive, appending a zero byte includes the cutoff itself.
+    cutoff.push(0);
+    table.delete_range(TOPIC_KEY_PREFIX, cutoff)?;
+
+    Ok(Some(last))
+}
+
+/// Removes time index entries of deleted batches up to the record `removed`.
+///
+/// The last entry at or before `removed` is kept, its batch may still have
+/// retained records.
+fn prune_time_index<T: Topic>(table: &TableImpl<T>, removed: &[u8]) -> Result<()> {
+    let prefix = format!("{}:", TOPIC_TIME_INDEX_KEY_PREFIX);
+    let mut keep: Option<Vec<u8>> = None;
+
+    let mut iter = table.prefix_iterator(&prefix);
+    loop {
+        if !iter.valid() {
+            break;
+        }
+        let (key, value) = match iter.item() {
+            Some(item) => item,
+            None => break,
+        };
+        if !key.starts_with(prefix.as_bytes()) || value > removed {
+            break;
+        }
+
+        keep = Some(key.to_vec());
+        iter.next();
+    }
+
+    if let Some(keep) = keep {
+        table.delete_range(prefix, keep)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the smallest checkpoint committed by any named iterator of the topic.
+fn min_checkpoint<T: Topic>(table: &TableImpl<T>) -> Option<Vec<u8>> {
+    let prefix = format!("{}:", TOPIC_ITERATOR_KEY_PREFIX);
+    let mut result: Option<Vec<u8>> = None;
+
+    let mut iter = table.prefix_iterator(&prefix);
+    loop {
+        if !iter.valid() {
+            break;
+        }
+        let (key, value) = match iter.item() {
+            Some(item) => item,
+            None => break,
+        };
+        if !key.starts_with(prefix.as_bytes()) {
+            break;
+        }
+
+        match &result {
+            Some(min) if min.as_slice() <= value => {}
+            _ => result = Some(value.to_vec()),
+        }
+
+        iter.next();
+    }
+
+    result
+}
+
+/// Handle of a background thread which periodically enforces topic retention.
+///
+/// The thread is stopped when the handle is dropped.
+pub struct RetentionTask {
+    stop: Option<mpsc::Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl RetentionTask {
+    pub fn spawn<T>(table: TableImpl<T>, retention: Retention, interval: Duration) -> Self
+    where
+        T: Topic + Send + 'static,
+    {
+        let (stop, receiver) = mpsc::channel::<()>();
+
+        let handle = std::thread::spawn(move || loop {
+            match receiver.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => {
+                    let _ = enforce(&table, &retention);
+                }
+                _ => break,
+            }
+        });
+
+        Self {
+            stop: Some(stop),
+            handle: Some(handle),
+        }
+    }
+
+    /// Stops the background thread and waits for it to finish.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        // NOTE: Dropping the sender disconnects the channel and wakes the thread.
+        self.stop.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for RetentionTask {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::Duration};
+
+    use crate::{
+        builder::StructDB,
+        caches::Caches,
+        iterator_batch::BatchIterator,
+        table::Table,
+        topic::{Topic, TopicImpl},
+    };
+
+    use super::Retention;
+
+    struct MyTopic;
+
+    impl Table for MyTopic {
+        const NAME: &'static str = "my-topic";
+    }
+
+    impl Topic for MyTopic {
+        fn retention(policy: &mut Retention) {
+            policy.max_records = Some(10);
+        }
+    }
+
+    fn append_records(topic: &mut TopicImpl<MyTopic>, count: usize) {
+        for _ in 0..count {
+            topic.append(&vec![1; 32]).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_retention_max_records() {
+        let _ = fs::remove_dir_all("test_retention_max_records.db");
+        let db = StructDB::builder("test_retention_max_records.db", Caches::default())
+            .with_struct::<MyTopic>()
+            .build()
+            .unwrap();
+
+        let mut topic = db.make_topic::<MyTopic>();
+        append_records(&mut topic, 30);
+
+        {
+            // NOTE: Committed iterator keeps records it has not consumed yet.
+            let mut iter = topic.window("iter1", 15);
+            assert_eq!(iter.next().unwrap().len(), 15);
+        }
+
+        let last = topic.enforce_retention().unwrap().unwrap();
+        assert_eq!(last.to_u128(), 15);
+        assert_eq!(topic.iter().count(), 15);
+
+        let mut policy = topic.retention();
+        policy.force = true;
+        let last = super::enforce(&topic.table, &policy).unwrap().unwrap();
+        assert_eq!(last.to_u128(), 20);
+        assert_eq!(topic.iter().count(), 10);
+        assert_eq!(topic.iter().next().unwrap().key.to_u128(), 21);
+
+        assert!(topic.enforce_retention().unwrap().is_none());
+
+        // NOTE: Appending continues after the retained records.
+        append_records(&mut topic, 1);
+        assert_eq!(db.make_topic::<MyTopic>().next_insert.to_u128(), 32);
+
+        // NOTE: Checkpoint of iter1 is older than all records, nothing is removed.
+        assert!(topic.enforce_retention().unwrap().is_none());
+        assert_eq!(topic.iter().count(), 11);
+    }
+
+    #[test]
+    fn test_retention_max_bytes() {
+        let _ = fs::remove_dir_all("test_retention_max_bytes.db");
+        let db = StructDB::builder("test_retention_max_bytes.db", Caches::default())
+            .with_struct::<MyTopic>()
+            .build()
+            .unwrap();
+
+        let mut topic = db.make_topic::<MyTopic>();
+        append_records(&mut topic, 10);
+
+        let policy = Retention {
+            max_bytes: Some(100),
+            ..Default::default()
+        };
+        super::enforce(&topic.table, &policy).unwrap();
+        assert_eq!(topic.iter().count(), 3);
+    }
+
+    #[test]
+    fn test_retention_max_age() {
+        let _ = fs::remove_dir_all("test_retention_max_age.db");
+        let db = StructDB::builder("test_retention_max_age.db", Caches::default())
+            .with_struct::<MyTopic>()
+            .build()
+            .unwrap();
+
+        let mut topic = db.make_topic::<MyTopic>();
+        append_records(&mut topic, 3);
+
+        // NOTE: Records carry the second of their append, not of the append before.
+        std::thread::sleep(Duration::from_millis(2100));
+        append_records(&mut topic, 2);
+
+        let policy = Retention {
+            max_age: Some(Duration::from_secs(1)),
+            ..Default::default()
+        };
+        super::enforce(&topic.table, &policy).unwrap();
+        assert_eq!(topic.iter().count(), 2);
+        assert_eq!(topic.iter().next().unwrap().key.to_u128(), 4);
+    }
+
+    #[test]
+    fn test_retention_task() {
+        let _ = fs::remove_dir_all("test_retention_task.db");
+        let db = StructDB::builder("test_retention_task.db", Caches::default())
+            .with_struct::<MyTopic>()
+            .build()
+            .unwrap();
+
+        let mut topic = db.make_topic::<MyTopic>();
+        append_records(&mut topic, 25);
+
+        let task = topic.spawn_retention(Duration::from_millis(10));
+        std::thread::sleep(Duration::from_millis(200));
+        task.stop();
+
+        assert_eq!(topic.iter().count(), 10);
+    }
+}
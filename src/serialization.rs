@@ -1,6 +1,16 @@
 use crate::errors::{Error, Result};
 use byte_counter::counter::ByteCounter;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// Encodes any serializable value with bincode.
+pub fn serialize<V: Serialize + ?Sized>(value: &V) -> Result<Vec<u8>> {
+    bincode::serialize(value).map_err(|err| Error::SerializationFailed(err.to_string()))
+}
+
+/// Decodes a value previously encoded with `serialize`.
+pub fn deserialize<V: DeserializeOwned>(encoded: &[u8]) -> Result<V> {
+    bincode::deserialize(encoded).map_err(|err| Error::DeserializationFailed(err.to_string()))
+}
 
 pub trait BinCode {
     fn to_bytes(&self) -> Result<Vec<u8>>
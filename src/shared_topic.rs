@@ -0,0 +1,133 @@
+use std::sync::{Mutex, MutexGuard};
+
+use byte_counter::counter::ByteCounter;
+
+use crate::{
+    errors::Result,
+    record::{Record, SeqRecord},
+    topic::{Topic, TopicImpl},
+};
+
+/// Topic handle which can be shared between threads via `Arc`.
+///
+/// Appends take `&self`, sequence numbers are allocated and written under a
+/// lock, so records of concurrent producers never share a key and are stored
+/// in key order. Handles returned by `StructDB::make_shared_topic` are shared
+/// per column family, appending through a `TopicImpl` of the same topic at the
+/// same time is not coordinated with them.
+pub struct SharedTopic<T> {
+    inner: Mutex<TopicImpl<T>>,
+}
+
+impl<T> SharedTopic<T>
+where
+    T: Topic,
+{
+    pub fn new(topic: TopicImpl<T>) -> Self {
+        Self {
+            inner: Mutex::new(topic),
+        }
+    }
+
+    pub fn append(&self, value: &Record) -> Result<SeqRecord> {
+        self.lock().append(value)
+    }
+
+    /// Appends all values in a single write batch with consecutive keys.
+    pub fn append_batch(&self, values: &[Record]) -> Result<Vec<SeqRecord>> {
+        self.lock().append_batch(values)
+    }
+
+    /// Key which is assigned to the next appended record.
+    pub fn next_insert(&self) -> ByteCounter {
+        self.lock().next_insert.clone()
+    }
+
+    /// Exclusive access to the underlying topic, producers are blocked until the
+    /// guard is dropped.
+    pub fn lock(&self) -> MutexGuard<'_, TopicImpl<T>> {
+        // NOTE: A panicked producer leaves `next_insert` consistent, the write either happened or not.
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Creates a separate handle for reading, which follows the appends of this one.
+    ///
+    /// `next_insert` of the reader is a copy taken now, iterators and lag of the
+    /// reader follow the records and last insert marker written by this handle.
+    /// The reader must not be used to append.
+    pub fn reader(&self) -> TopicImpl<T> {
+        let topic = self.lock();
+        TopicImpl::with_notifier(topic.table.clone_handle(), topic.notifier.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::Arc, thread};
+
+    use crate::{
+        builder::StructDB, caches::Caches, errors::Error, iterator_batch::BatchIterator,
+        table::Table, topic::Topic,
+    };
+
+    struct MyTopic;
+
+    impl Table for MyTopic {
+        const NAME: &'static str = "my-topic";
+    }
+
+    impl Topic for MyTopic {}
+
+    struct OtherTopic;
+
+    impl Table for OtherTopic {
+        const NAME: &'static str = "my-topic";
+    }
+
+    impl Topic for OtherTopic {}
+
+    #[test]
+    fn test_shared_topic_concurrent_append() {
+        let _ = fs::remove_dir_all("test_shared_topic_concurrent_append.db");
+        let db = StructDB::builder("test_shared_topic_concurrent_append.db", Caches::default())
+            .with_struct::<MyTopic>()
+            .build()
+            .unwrap();
+
+        let topic = db.make_shared_topic::<MyTopic>().unwrap();
+        assert!(Arc::ptr_eq(
+            &topic,
+            &db.make_shared_topic::<MyTopic>().unwrap()
+        ));
+
+        let db = &db;
+        thread::scope(|scope| {
+            for worker in 0..4u8 {
+                scope.spawn(move || {
+                    let topic = db.make_shared_topic::<MyTopic>().unwrap();
+                    for _ in 0..25 {
+                        topic.append(&vec![worker; 8]).unwrap();
+                    }
+                    topic.append_batch(&vec![vec![worker; 8]; 25]).unwrap();
+                });
+            }
+        });
+
+        let reader = topic.reader();
+        let keys: Vec<u128> = reader.iter().map(|r| r.key.to_u128()).collect();
+        assert_eq!(keys, (1..=200).collect::<Vec<u128>>());
+        assert_eq!(topic.next_insert().to_u128(), 201);
+        assert_eq!(reader.next_insert.to_u128(), 201);
+
+        // NOTE: Lag of a reader follows appends made after the reader was created.
+        topic.append(&vec![9; 8]).unwrap();
+        assert_eq!(reader.window("lagging", 10).tail_distance(), 201);
+
+        assert!(matches!(
+            db.make_shared_topic::<OtherTopic>(),
+            Err(Error::SharedTopicTypeMismatch(_))
+        ));
+    }
+}
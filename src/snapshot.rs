@@ -5,12 +5,21 @@ use rocksdb::BoundColumnFamily;
 use crate::{
     database::Database,
     errors::{Error, Result},
+    key_encoding::prefix_end,
+    record::SeqRecord,
+    table::{Table, TableImpl},
+    topic::{Topic, TOPIC_KEY_END, TOPIC_KEY_PREFIX},
+    typed_table::TypedTable,
 };
 
 /// A snapshot of `Database` with specified column family.
+///
+/// All reads, including reads through `table` views of other column families,
+/// observe the database at the sequence number of the snapshot. The database
+/// is borrowed for the lifetime of the snapshot, use `from_raw` or `from_table`
+/// to take a snapshot without a `Database`.
 pub struct DatabaseSnapshot<'a> {
     pub cf_name: &'a str,
-    pub db: &'a Database,
     pub column_family: Arc<BoundColumnFamily<'a>>,
     pub snapshot: rocksdb::Snapshot<'a>,
 }
@@ -18,28 +27,229 @@ pub struct DatabaseSnapshot<'a> {
 /// Implementation of `DBSnapshot` type.
 impl<'a> DatabaseSnapshot<'a> {
     pub fn new(db: &'a Database, cf_name: &'a str) -> Result<Self> {
-        db.raw
-            .cf_handle(cf_name)
+        Self::from_raw(&db.raw, cf_name)
+    }
+
+    pub fn from_raw(db: &'a rocksdb::DB, cf_name: &'a str) -> Result<Self> {
+        db.cf_handle(cf_name)
             .map(|result| {
                 return Self {
                     cf_name,
-                    db,
                     column_family: result.clone(),
-                    snapshot: db.raw.snapshot(),
+                    snapshot: db.snapshot(),
                 };
             })
             .ok_or_else(|| Error::ColumnFamilyNotFound(cf_name.to_string()))
     }
+
+    /// Snapshot with the column family of the given table.
+    pub fn from_table<T: Table>(table: &'a TableImpl<T>) -> Result<Self> {
+        Self::from_raw(table.db(), &table.name)
+    }
+
+    /// Reads another table at the sequence number of this snapshot.
+    pub fn table<'s, T: Table>(&'s self, table: &'s TableImpl<T>) -> TableSnapshot<'s, T> {
+        TableSnapshot {
+            snapshot: &self.snapshot,
+            table,
+        }
+    }
+
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>> {
+        self.snapshot
+            .get_cf(&self.column_family, key)
+            .map_err(Into::into)
+    }
+
+    /// Returns values of the given keys in the order of the keys.
+    pub fn multi_get<K, I>(&self, keys: I) -> Result<Vec<Option<Vec<u8>>>>
+    where
+        K: AsRef<[u8]>,
+        I: IntoIterator<Item = K>,
+    {
+        let cf = &self.column_family;
+        self.snapshot
+            .multi_get_cf(keys.into_iter().map(|key| (cf, key)))
+            .into_iter()
+            .map(|value| value.map_err(Into::into))
+            .collect()
+    }
+
+    pub fn iter_start(&'_ self) -> rocksdb::DBIterator<'_> {
+        self.snapshot
+            .iterator_cf(&self.column_family, rocksdb::IteratorMode::Start)
+    }
+
+    /// Iterates over keys in `[from, to)`, either bound can be omitted.
+    pub fn iter_range(&'_ self, from: Option<&[u8]>, to: Option<&[u8]>) -> rocksdb::DBIterator<'_> {
+        let read_config = bounded_read_config(Default::default(), from, to);
+        self.snapshot.iterator_cf_opt(
+            &self.column_family,
+            read_config,
+            rocksdb::IteratorMode::Start,
+        )
+    }
+
+    /// Iterates over keys starting with the given prefix.
+    pub fn prefix_iterator<P: AsRef<[u8]>>(&'_ self, prefix: P) -> rocksdb::DBIterator<'_> {
+        let prefix = prefix.as_ref();
+        self.iter_range(Some(prefix), prefix_end(prefix).as_deref())
+    }
+}
+
+/// View of a table at the sequence number of a `DatabaseSnapshot`.
+pub struct TableSnapshot<'s, T> {
+    snapshot: &'s rocksdb::Snapshot<'s>,
+    table: &'s TableImpl<T>,
+}
+
+impl<'s, T> TableSnapshot<'s, T>
+where
+    T: Table,
+{
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>> {
+        self.snapshot
+            .get_cf_opt(&self.table.cf(), key, self.table.new_read_config())
+            .map_err(Into::into)
+    }
+
+    /// Returns values of the given keys in the order of the keys.
+    pub fn multi_get<K, I>(&self, keys: I) -> Result<Vec<Option<Vec<u8>>>>
+    where
+        K: AsRef<[u8]>,
+        I: IntoIterator<Item = K>,
+    {
+        let cf = self.table.cf();
+        self.snapshot
+            .multi_get_cf_opt(
+                keys.into_iter().map(|key| (&cf, key)),
+                self.table.new_read_config(),
+            )
+            .into_iter()
+            .map(|value| value.map_err(Into::into))
+            .collect()
+    }
+
+    pub fn iter_start(&self) -> rocksdb::DBIterator<'s> {
+        self.iter_range(None, None)
+    }
+
+    /// Iterates over keys in `[from, to)`, either bound can be omitted.
+    pub fn iter_range(&self, from: Option<&[u8]>, to: Option<&[u8]>) -> rocksdb::DBIterator<'s> {
+        let read_config = bounded_read_config(self.table.new_read_config(), from, to);
+        self.snapshot
+            .iterator_cf_opt(&self.table.cf(), read_config, rocksdb::IteratorMode::Start)
+    }
+
+    /// Iterates over keys starting with the given prefix.
+    pub fn prefix_iterator<P: AsRef<[u8]>>(&self, prefix: P) -> rocksdb::DBIterator<'s> {
+        let prefix = prefix.as_ref();
+        self.iter_range(Some(prefix), prefix_end(prefix).as_deref())
+    }
+}
+
+impl<'s, T> TableSnapshot<'s, T>
+where
+    T: TypedTable,
+{
+    pub fn get_typed(&self, key: &T::Key) -> Result<Option<T::Value>> {
+        match self.get(T::encode_key(key)?)? {
+            Some(value) => T::decode_value(&value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn iter_typed(&self) -> impl Iterator<Item = Result<(T::Key, T::Value)>> + 's {
+        decode_typed::<T>(self.iter_start())
+    }
+
+    /// Iterates over typed keys in `[from, to)` in the byte order of the encoded keys.
+    pub fn range_typed(
+        &self,
+        from: Option<&T::Key>,
+        to: Option<&T::Key>,
+    ) -> Result<impl Iterator<Item = Result<(T::Key, T::Value)>> + 's> {
+        let from = from.map(T::encode_key).transpose()?;
+        let to = to.map(T::encode_key).transpose()?;
+        Ok(decode_typed::<T>(
+            self.iter_range(from.as_deref(), to.as_deref()),
+        ))
+    }
+}
+
+impl<'s, T> TableSnapshot<'s, T>
+where
+    T: Topic,
+{
+    /// Records of the topic as of the snapshot.
+    pub fn records(&self) -> impl Iterator<Item = Result<SeqRecord>> + 's {
+        self.iter_range(
+            Some(TOPIC_KEY_PREFIX.as_bytes()),
+            Some(TOPIC_KEY_END.as_bytes()),
+        )
+        .map(|item| {
+            let (key, value) = item?;
+            Ok(SeqRecord::from(Some((key.as_ref(), value.as_ref()))))
+        })
+    }
+}
+
+fn decode_typed<'s, T: TypedTable>(
+    iter: rocksdb::DBIterator<'s>,
+) -> impl Iterator<Item = Result<(T::Key, T::Value)>> + 's
+where
+    T: 's,
+{
+    iter.map(|item| {
+        let (key, value) = item?;
+        Ok((T::decode_key(&key)?, T::decode_value(&value)?))
+    })
+}
+
+fn bounded_read_config(
+    mut read_config: rocksdb::ReadOptions,
+    from: Option<&[u8]>,
+    to: Option<&[u8]>,
+) -> rocksdb::ReadOptions {
+    if let Some(from) = from {
+        read_config.set_iterate_lower_bound(from.to_vec());
+    }
+    if let Some(to) = to {
+        read_config.set_iterate_upper_bound(to.to_vec());
+    }
+    read_config
 }
 
 #[cfg(test)]
 mod tests {
     use std::fs;
 
-    use crate::database::Database;
+    use crate::{
+        builder::StructDB, caches::Caches, database::Database, table::Table, topic::Topic,
+        typed_table::TypedTable,
+    };
 
     use super::DatabaseSnapshot;
 
+    struct Orders;
+
+    impl Table for Orders {
+        const NAME: &'static str = "orders";
+    }
+
+    impl TypedTable for Orders {
+        type Key = u64;
+        type Value = String;
+    }
+
+    struct Events;
+
+    impl Table for Events {
+        const NAME: &'static str = "events";
+    }
+
+    impl Topic for Events {}
+
     #[test]
     fn test_db_snapshot_from() {
         let _ = fs::remove_dir_all("test_db_snapshot_from.db");
@@ -57,4 +267,49 @@ mod tests {
         let snap = DatabaseSnapshot::new(&db, cf_name).unwrap();
         assert_eq!(snap.cf_name, "stream1");
     }
+
+    #[test]
+    fn test_snapshot_reads() {
+        let _ = fs::remove_dir_all("test_snapshot_reads.db");
+        let db = StructDB::builder("test_snapshot_reads.db", Caches::default())
+            .with_struct::<Orders>()
+            .with_struct::<Events>()
+            .build()
+            .unwrap();
+
+        let orders = db.make_typed_table::<Orders>();
+        let mut events = db.make_topic::<Events>();
+        orders.insert(&1, &"first".to_string()).unwrap();
+        events.append(&vec![1; 8]).unwrap();
+
+        let snap = db.snapshot().unwrap();
+
+        orders.insert(&1, &"changed".to_string()).unwrap();
+        orders.insert(&2, &"second".to_string()).unwrap();
+        events.append(&vec![2; 8]).unwrap();
+
+        let orders_at = snap.table(&orders.table);
+        assert_eq!(orders_at.get_typed(&1).unwrap(), Some("first".to_string()));
+        assert_eq!(orders_at.get_typed(&2).unwrap(), None);
+        let keys = [
+            Orders::encode_key(&1).unwrap(),
+            Orders::encode_key(&2).unwrap(),
+        ];
+        let values = orders_at.multi_get(keys).unwrap();
+        assert!(values[0].is_some() && values[1].is_none());
+        assert_eq!(orders_at.iter_typed().count(), 1);
+
+        let records: Vec<_> = snap
+            .table(&events.table)
+            .records()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].value, vec![1; 8]);
+
+        // NOTE: Snapshot of a single table reads its own column family directly.
+        let topic_snap = events.snapshot().unwrap();
+        assert_eq!(topic_snap.prefix_iterator("topic:").count(), 2);
+        assert_eq!(orders.get(&1).unwrap(), Some("changed".to_string()));
+    }
 }
@@ -1,8 +1,16 @@
-use std::{marker::PhantomData, sync::Arc};
+use std::{
+    marker::PhantomData,
+    sync::{mpsc::Receiver, Arc},
+};
 
 use crate::handle::{BoundedCfHandle, CfHandle, UnboundedCfHandle};
 
+use crate::batch::Batch;
 use crate::caches::Caches;
+use crate::key_encoding::prefix_end;
+use crate::locks::KeyLocks;
+use crate::snapshot::DatabaseSnapshot;
+use crate::watch::{Decode, WatchEvent, Watchers};
 
 pub trait Table {
     const NAME: &'static str;
@@ -24,8 +32,11 @@ pub trait Table {
 pub struct TableImpl<T> {
     pub name: String,
     //
+    shard: Option<String>,
     cf: CfHandle,
     db: Arc<rocksdb::DB>,
+    locks: Arc<KeyLocks>,
+    watchers: Arc<Watchers>,
     write_config: rocksdb::WriteOptions,
     read_config: rocksdb::ReadOptions,
     _ty: PhantomData<T>,
@@ -66,8 +77,11 @@ where
         T::read_options(&mut read_config);
 
         Self {
+            shard: shard.cloned(),
             cf,
             db,
+            locks: Default::default(),
+            watchers: Default::default(),
             name,
             write_config,
             read_config,
@@ -75,6 +89,40 @@ where
         }
     }
 
+    /// Creates another handle to the same column family with fresh read and write options.
+    pub fn clone_handle(&self) -> Self {
+        Self {
+            shard: self.shard.clone(),
+            cf: self.cf,
+            db: self.db.clone(),
+            locks: self.locks.clone(),
+            watchers: self.watchers.clone(),
+            name: self.name.clone(),
+            write_config: self.new_write_config(),
+            read_config: self.new_read_config(),
+            _ty: Default::default(),
+        }
+    }
+
+    /// Handle of another table in the same shard, sharing locks and watchers with this one.
+    pub(crate) fn sibling<U: Table>(&self) -> TableImpl<U> {
+        TableImpl::new(self.db.clone(), self.shard.as_ref())
+            .with_locks(self.locks.clone())
+            .with_watchers(self.watchers.clone())
+    }
+
+    /// Shares the key locks of conditional writes with other handles of the database.
+    pub(crate) fn with_locks(mut self, locks: Arc<KeyLocks>) -> Self {
+        self.locks = locks;
+        self
+    }
+
+    /// Shares the watch subscriptions with other handles of the database.
+    pub(crate) fn with_watchers(mut self, watchers: Arc<Watchers>) -> Self {
+        self.watchers = watchers;
+        self
+    }
+
     pub fn cf(&'_ self) -> BoundedCfHandle<'_> {
         BoundedCfHandle::new(self.cf.0)
     }
@@ -88,6 +136,11 @@ where
         &self.db
     }
 
+    /// Point in time view of the database with the column family of the table.
+    pub fn snapshot(&'_ self) -> crate::errors::Result<DatabaseSnapshot<'_>> {
+        DatabaseSnapshot::from_table(self)
+    }
+
     #[inline]
     pub fn read_config(&self) -> &rocksdb::ReadOptions {
         &self.read_config
@@ -126,12 +179,30 @@ where
         db_get(self.db.as_ref(), self.cf, key.as_ref(), &self.read_config)
     }
 
+    /// Reads all keys with one batched lookup, values are returned in the order of `keys`.
+    pub fn multi_get<K, I>(
+        &self,
+        keys: I,
+    ) -> Result<Vec<Option<rocksdb::DBPinnableSlice<'_>>>, rocksdb::Error>
+    where
+        K: AsRef<[u8]>,
+        I: IntoIterator<Item = K>,
+    {
+        let keys: Vec<K> = keys.into_iter().collect();
+        self.db
+            .batched_multi_get_cf_opt(&self.cf, &keys, false, &self.read_config)
+            .into_iter()
+            .collect()
+    }
+
     #[inline]
     pub fn insert<K, V>(&self, key: K, value: V) -> Result<(), rocksdb::Error>
     where
         K: AsRef<[u8]>,
         V: AsRef<[u8]>,
     {
+        let _guard = self.locks.lock(&self.name, key.as_ref());
+
         fn db_insert(
             db: &rocksdb::DB,
             cf: CfHandle,
@@ -147,12 +218,19 @@ where
             key.as_ref(),
             value.as_ref(),
             &self.write_config,
-        )
+        )?;
+        self.locks.record_write(&self.name, key.as_ref());
+
+        self.watchers
+            .notify(&self.name, key.as_ref(), Some(value.as_ref()));
+        Ok(())
     }
 
     #[allow(unused)]
     #[inline]
     pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Result<(), rocksdb::Error> {
+        let _guard = self.locks.lock(&self.name, key.as_ref());
+
         fn db_remove(
             db: &rocksdb::DB,
             cf: CfHandle,
@@ -161,7 +239,233 @@ where
         ) -> Result<(), rocksdb::Error> {
             db.delete_cf_opt(&cf, key, writeopts)
         }
-        db_remove(self.db.as_ref(), self.cf, key.as_ref(), &self.write_config)
+        db_remove(self.db.as_ref(), self.cf, key.as_ref(), &self.write_config)?;
+        self.locks.record_write(&self.name, key.as_ref());
+
+        self.watchers.notify(&self.name, key.as_ref(), None);
+        Ok(())
+    }
+
+    /// Deletes all keys in `[from, to)` with a single range tombstone.
+    ///
+    /// Space is reclaimed once compaction drops the tombstone, see `compact_range`.
+    /// Range deletions lock every key of the database while they are written.
+    pub fn delete_range<F, K>(&self, from: F, to: K) -> Result<(), rocksdb::Error>
+    where
+        F: AsRef<[u8]>,
+        K: AsRef<[u8]>,
+    {
+        let _guards = self.locks.lock_every();
+        self.delete_range_unlocked(from.as_ref(), to.as_ref())
+    }
+
+    /// Deletes all keys starting with `prefix`.
+    pub fn delete_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<(), rocksdb::Error> {
+        let prefix = prefix.as_ref();
+        let _guards = self.locks.lock_every();
+        match prefix_end(prefix) {
+            Some(end) => self.delete_range_unlocked(prefix, &end),
+            None => self.delete_from_unlocked(prefix),
+        }
+    }
+
+    /// Deletes all keys of the table.
+    pub fn truncate(&self) -> Result<(), rocksdb::Error> {
+        let _guards = self.locks.lock_every();
+        self.delete_from_unlocked(b"")
+    }
+
+    /// Compacts the keys in `[from, to)`, either bound can be omitted.
+    ///
+    /// Drops the data covered by range deletions instead of waiting for
+    /// background compaction, which blocks until it is done.
+    pub fn compact_range(&self, from: Option<&[u8]>, to: Option<&[u8]>) {
+        self.db.compact_range_cf(&self.cf, from, to);
+    }
+
+    fn delete_range_unlocked(&self, from: &[u8], to: &[u8]) -> Result<(), rocksdb::Error> {
+        // NOTE: Tombstones do not name their keys, watched keys are collected before.
+        let mut deleted = Vec::new();
+        for (start, end) in self.watchers.watched_ranges(&self.name, from, to) {
+            for item in self.iter_range(Some(&start), Some(&end)) {
+                deleted.push(item?.0);
+            }
+        }
+        deleted.sort_unstable();
+        deleted.dedup();
+
+        self.db
+            .delete_range_cf_opt(&self.cf, from, to, &self.write_config)?;
+        self.locks.record_write_all();
+
+        for key in deleted {
+            self.watchers.notify(&self.name, &key, None);
+        }
+        Ok(())
+    }
+
+    /// Deletes all keys from `from` up to and including the last key of the table.
+    fn delete_from_unlocked(&self, from: &[u8]) -> Result<(), rocksdb::Error> {
+        let mut iter = self.raw_iterator();
+        iter.seek_to_last();
+        iter.status()?;
+
+        match iter.key() {
+            Some(last) => {
+                // NOTE: Range end is exclusive, appending a zero byte includes the last key itself.
+                let mut end = last.to_vec();
+                end.push(0);
+                self.delete_range_unlocked(from, &end)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Adds a merge operand, combined with the stored value by the merge operator of the column family.
+    #[inline]
+    pub fn merge<K, V>(&self, key: K, value: V) -> Result<(), rocksdb::Error>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        let key = key.as_ref();
+        let _guard = self.locks.lock(&self.name, key);
+        self.db
+            .merge_cf_opt(&self.cf, key, value, &self.write_config)?;
+        self.locks.record_write(&self.name, key);
+
+        // NOTE: Operands are only combined on read, watchers receive the merged value.
+        if self.watchers.is_watched(&self.name) {
+            let merged = self.get(key)?;
+            self.watchers.notify(&self.name, key, merged.as_deref());
+        }
+        Ok(())
+    }
+
+    /// Writes `new` if the current value equals `expected`, `None` stands for a missing key.
+    ///
+    /// Writing `None` removes the key. Returns whether the value was written.
+    /// Atomic with respect to all writes through handles of the same `StructDB`
+    /// except raw `write_batch`.
+    pub fn compare_and_swap<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<bool, rocksdb::Error> {
+        let key = key.as_ref();
+        let _guard = self.locks.lock(&self.name, key);
+
+        let current = self.get(key)?;
+        if current.as_deref() != expected {
+            return Ok(false);
+        }
+
+        self.write_unlocked(key, new)?;
+        Ok(true)
+    }
+
+    /// Inserts the value unless the key exists, returns whether it was inserted.
+    pub fn insert_if_absent<K, V>(&self, key: K, value: V) -> Result<bool, rocksdb::Error>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        self.compare_and_swap(key, None, Some(value.as_ref()))
+    }
+
+    /// Replaces the value with the result of `f`, returning `None` from `f` removes the key.
+    ///
+    /// `f` receives the current value and runs without holding the key lock, so
+    /// it may write to any table. The result is written with `compare_and_swap`,
+    /// if the key was written meanwhile `f` runs again with the new value.
+    /// Returns the written value.
+    pub fn update<K, F>(&self, key: K, mut f: F) -> Result<Option<Vec<u8>>, rocksdb::Error>
+    where
+        K: AsRef<[u8]>,
+        F: FnMut(Option<&[u8]>) -> Option<Vec<u8>>,
+    {
+        let key = key.as_ref();
+        loop {
+            let current = self.get(key)?.map(|value| value.to_vec());
+            let new = f(current.as_deref());
+            if self.compare_and_swap(key, current.as_deref(), new.as_deref())? {
+                return Ok(new);
+            }
+        }
+    }
+
+    fn write_unlocked(&self, key: &[u8], value: Option<&[u8]>) -> Result<(), rocksdb::Error> {
+        match value {
+            Some(value) => self
+                .db
+                .put_cf_opt(&self.cf, key, value, &self.write_config)?,
+            None => self.db.delete_cf_opt(&self.cf, key, &self.write_config)?,
+        }
+        self.locks.record_write(&self.name, key);
+
+        self.watchers.notify(&self.name, key, value);
+        Ok(())
+    }
+
+    /// Receives every write of the key through handles of the same `StructDB`.
+    ///
+    /// Covers single and conditional writes, applied batches, transactions,
+    /// merges with their merged value and range deletions, only raw
+    /// `write_batch` is not reported. Values are reported as stored, see
+    /// `ExpiringTableImpl::watch` for decoded values of expiring tables.
+    pub fn watch<K: AsRef<[u8]>>(&self, key: K) -> Receiver<WatchEvent> {
+        self.watchers.watch_key(&self.name, key.as_ref(), None)
+    }
+
+    /// Receives every write of keys starting with `prefix`, see `watch`.
+    pub fn watch_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Receiver<WatchEvent> {
+        self.watchers
+            .watch_prefix(&self.name, prefix.as_ref(), None)
+    }
+
+    pub(crate) fn watch_decoded(&self, key: &[u8], decode: Decode) -> Receiver<WatchEvent> {
+        self.watchers.watch_key(&self.name, key, Some(decode))
+    }
+
+    pub(crate) fn watch_prefix_decoded(
+        &self,
+        prefix: &[u8],
+        decode: Decode,
+    ) -> Receiver<WatchEvent> {
+        self.watchers.watch_prefix(&self.name, prefix, Some(decode))
+    }
+
+    /// Applies the given batch atomically using the table write options.
+    ///
+    /// Keys of a raw batch are not known, it is written without key locks and
+    /// may interleave with `compare_and_swap` and `update`, and it is not
+    /// reported to watchers. Use `apply` for batches which must not.
+    ///
+    /// Running transactions treat every key as written by a raw batch.
+    #[inline]
+    pub fn write_batch(&self, batch: rocksdb::WriteBatch) -> Result<(), rocksdb::Error> {
+        self.db.write_opt(batch, &self.write_config)?;
+        self.locks.record_write_all();
+        Ok(())
+    }
+
+    /// Applies the batch atomically using the table write options, while the
+    /// keys of all staged writes are locked.
+    ///
+    /// Fails without writing anything if a unique index key of the batch is
+    /// owned by another row.
+    pub fn apply(&self, batch: Batch) -> crate::errors::Result<()> {
+        let _guards = self.locks.lock_all(batch.keys());
+        batch.verify(&self.db)?;
+        let (inner, writes) = batch.into_parts();
+        self.db.write_opt(inner, &self.write_config)?;
+        for write in &writes {
+            self.locks.record_write(&write.cf_name, &write.key);
+        }
+
+        self.watchers.notify_all(&writes);
+        Ok(())
     }
 
     #[inline]
@@ -202,6 +506,39 @@ where
         ))
     }
 
+    /// Iterates over keys in `[from, to)` using RocksDB iterate bounds, either bound can be omitted.
+    pub fn iter_range(&'_ self, from: Option<&[u8]>, to: Option<&[u8]>) -> rocksdb::DBIterator<'_> {
+        let read_config = self.new_bounded_read_config(from, to);
+        self.db
+            .iterator_cf_opt(&self.cf, read_config, rocksdb::IteratorMode::Start)
+    }
+
+    /// Iterates over keys in `[from, to)` from the last key to the first one.
+    pub fn iter_range_rev(
+        &'_ self,
+        from: Option<&[u8]>,
+        to: Option<&[u8]>,
+    ) -> rocksdb::DBIterator<'_> {
+        let read_config = self.new_bounded_read_config(from, to);
+        self.db
+            .iterator_cf_opt(&self.cf, read_config, rocksdb::IteratorMode::End)
+    }
+
+    fn new_bounded_read_config(
+        &self,
+        from: Option<&[u8]>,
+        to: Option<&[u8]>,
+    ) -> rocksdb::ReadOptions {
+        let mut read_config = self.new_read_config();
+        if let Some(from) = from {
+            read_config.set_iterate_lower_bound(from.to_vec());
+        }
+        if let Some(to) = to {
+            read_config.set_iterate_upper_bound(to.to_vec());
+        }
+        read_config
+    }
+
     #[allow(unused)]
     pub fn prefix_iterator<P>(&'_ self, prefix: P) -> rocksdb::DBRawIterator<'_>
     where
@@ -224,3 +561,178 @@ where
         self.db.raw_iterator_cf_opt(&self.cf, read_config)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::mpsc, thread};
+
+    use crate::{batch::Batch, builder::StructDB, caches::Caches};
+
+    use super::Table;
+
+    struct Leases;
+
+    impl Table for Leases {
+        const NAME: &'static str = "leases";
+    }
+
+    #[test]
+    fn test_conditional_writes() {
+        let _ = fs::remove_dir_all("test_conditional_writes.db");
+        let db = StructDB::builder("test_conditional_writes.db", Caches::default())
+            .with_struct::<Leases>()
+            .build()
+            .unwrap();
+
+        let leases = db.make_table::<Leases>();
+        assert!(leases.insert_if_absent("job", "worker-1").unwrap());
+        assert!(!leases.insert_if_absent("job", "worker-2").unwrap());
+
+        let swapped = leases
+            .compare_and_swap("job", Some(b"worker-2"), Some(b"worker-3"))
+            .unwrap();
+        assert!(!swapped);
+        let swapped = leases
+            .compare_and_swap("job", Some(b"worker-1"), Some(b"worker-3"))
+            .unwrap();
+        assert!(swapped);
+        assert_eq!(
+            leases.get("job").unwrap().as_deref(),
+            Some(&b"worker-3"[..])
+        );
+
+        assert!(leases
+            .compare_and_swap("job", Some(b"worker-3"), None)
+            .unwrap());
+        assert!(!leases.contains_key("job").unwrap());
+
+        // NOTE: Handles of the same database share the key locks, no increment is lost.
+        let db = &db;
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(move || {
+                    let leases = db.make_table::<Leases>();
+                    for _ in 0..50 {
+                        leases
+                            .update("counter", |old| {
+                                let old = old
+                                    .map_or(0, |old| u64::from_be_bytes(old.try_into().unwrap()));
+                                Some((old + 1).to_be_bytes().to_vec())
+                            })
+                            .unwrap();
+                    }
+                });
+            }
+        });
+
+        let counter = leases.get("counter").unwrap().unwrap();
+        assert_eq!(
+            u64::from_be_bytes(counter.as_ref().try_into().unwrap()),
+            200
+        );
+
+        assert_eq!(leases.update("counter", |_| None).unwrap(), None);
+        assert!(!leases.contains_key("counter").unwrap());
+    }
+
+    #[test]
+    fn test_range_deletion() {
+        let _ = fs::remove_dir_all("test_range_deletion.db");
+        let db = StructDB::builder("test_range_deletion.db", Caches::default())
+            .with_struct::<Leases>()
+            .build()
+            .unwrap();
+
+        let leases = db.make_table::<Leases>();
+        for group in ["a", "b", "c"] {
+            for i in 0..10 {
+                leases.insert(format!("{}:{}", group, i), b"x").unwrap();
+            }
+        }
+        leases.insert([0xff, 0xff], b"x").unwrap();
+        leases.insert([0xff, 0xff, 0x01], b"x").unwrap();
+
+        leases.delete_range("a:2", "a:5").unwrap();
+        leases.delete_prefix("b:").unwrap();
+        leases.delete_prefix([0xff]).unwrap();
+        leases.compact_range(None, None);
+
+        let keys: Vec<String> = leases
+            .iter_start()
+            .map(|item| String::from_utf8(item.unwrap().0.to_vec()).unwrap())
+            .collect();
+        assert_eq!(keys.len(), 17);
+        assert!(keys.contains(&"a:1".to_string()) && keys.contains(&"a:5".to_string()));
+        assert!(!keys.contains(&"a:2".to_string()));
+        assert!(keys.iter().all(|key| !key.starts_with("b:")));
+
+        leases.truncate().unwrap();
+        assert_eq!(leases.iter_start().count(), 0);
+        leases.truncate().unwrap();
+    }
+
+    #[test]
+    fn test_locked_batches() {
+        let _ = fs::remove_dir_all("test_locked_batches.db");
+        let db = StructDB::builder("test_locked_batches.db", Caches::default())
+            .with_struct::<Leases>()
+            .build()
+            .unwrap();
+
+        let leases = db.make_table::<Leases>();
+
+        // NOTE: Each write lands while `f` runs, `update` runs it again on the written value.
+        let writes: [(&dyn Fn(), Option<&[u8]>); 2] = [
+            (
+                &|| {
+                    let mut batch = Batch::default();
+                    batch.put(&leases, "job", "batch");
+                    leases.apply(batch).unwrap();
+                },
+                Some(&b"batch"[..]),
+            ),
+            (&|| leases.delete_prefix("jo").unwrap(), None),
+        ];
+        let db = &db;
+        for (write, written) in writes {
+            leases.insert("job", "old").unwrap();
+            let (started, wait) = mpsc::channel();
+            let (written_tx, done) = mpsc::channel::<()>();
+            let seen = thread::scope(|scope| {
+                let updater = scope.spawn(move || {
+                    let mut seen = vec![];
+                    db.make_table::<Leases>()
+                        .update("job", |current| {
+                            seen.push(current.map(|value| value.to_vec()));
+                            if seen.len() == 1 {
+                                started.send(()).unwrap();
+                                done.recv().unwrap();
+                            }
+                            Some(b"update".to_vec())
+                        })
+                        .unwrap();
+                    seen
+                });
+                wait.recv().unwrap();
+                write();
+                written_tx.send(()).unwrap();
+                updater.join().unwrap()
+            });
+            assert_eq!(
+                seen,
+                vec![Some(b"old".to_vec()), written.map(|value| value.to_vec())]
+            );
+            assert_eq!(leases.get("job").unwrap().as_deref(), Some(&b"update"[..]));
+        }
+
+        // NOTE: `f` runs without the key lock, it may write the same table.
+        let updated = leases
+            .update("job", |_| {
+                leases.insert("other", "x").unwrap();
+                Some(b"nested".to_vec())
+            })
+            .unwrap();
+        assert_eq!(updated.as_deref(), Some(&b"nested"[..]));
+        assert!(leases.contains_key("other").unwrap());
+    }
+}
@@ -10,6 +10,13 @@ pub fn epoch_ns() -> u128 {
     }
 }
 
+pub fn epoch_millis() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(time) => time.as_millis() as u64,
+        Err(_) => panic!("Unable to determine time."),
+    }
+}
+
 pub fn epoch_secs() -> u64 {
     match SystemTime::now().duration_since(UNIX_EPOCH) {
         Ok(time) => time.as_secs(),
@@ -1,20 +1,45 @@
-use byte_counter::counter::ByteCounter;
-
-use crate::errors::{Error, Result};
-use crate::iterator_batch::IteratorBatch;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::batch::Batch;
+use crate::consumers::Consumers;
+use crate::errors::Result;
+use crate::follow::{Notifier, TopicStream};
+use crate::iterator_batch::{CommitMode, IteratorBatch};
+use crate::iterator_range::IteratorRange;
 use crate::iterator_single::IteratorSingle;
 use crate::record::{Record, SeqRecord};
+use crate::retention::{self, Retention, RetentionTask};
+use crate::snapshot::DatabaseSnapshot;
 use crate::table::{Table, TableImpl};
+use crate::timestamp::epoch_ns;
+use crate::writer::WriteBuffer;
+use byte_counter::{counter::ByteCounter, timestamp::Timestamp};
 
 pub const TOPIC_ITERATOR_KEY_PREFIX: &str = "iter";
 pub const TOPIC_KEY_PREFIX: &str = "topic";
+/// Upper bound for keys of topic records, `;` follows `:` in byte order.
+pub const TOPIC_KEY_END: &str = "topic;";
 pub const TOPIC_LAST_INSERT_KEY: &str = "last";
+/// Prefix of the time index, entries map an append time to the first record of the batch.
+pub const TOPIC_TIME_INDEX_KEY_PREFIX: &str = "time";
+
+/// Key ranges `[from, to)` reserved by transactions which ended without commit.
+pub(crate) type Discarded = Arc<Mutex<Vec<(ByteCounter, ByteCounter)>>>;
 
-pub trait Topic: Table {}
+pub trait Topic: Table {
+    fn retention(policy: &mut Retention) {
+        let _unused = policy;
+    }
+}
 
 pub struct TopicImpl<T> {
     pub table: TableImpl<T>,
     pub next_insert: ByteCounter,
+    pub notifier: Arc<Notifier>,
+    discarded: Discarded,
 }
 
 impl<T> TopicImpl<T>
@@ -22,17 +47,38 @@ where
     T: Topic,
 {
     pub fn new(table: TableImpl<T>) -> Self {
+        Self::with_notifier(table, Default::default())
+    }
+
+    /// Creates a topic which signals appends through the given notifier, topics
+    /// sharing the notifier wake each other's followers.
+    pub fn with_notifier(table: TableImpl<T>, notifier: Arc<Notifier>) -> Self {
         let mut topic = Self {
             table: table,
             next_insert: ByteCounter::new_with_prefix(TOPIC_KEY_PREFIX.to_string()),
+            notifier,
+            discarded: Default::default(),
         };
         topic.seek_last();
 
         topic
     }
 
+    /// Restores `next_insert` from the persisted last insert marker.
+    ///
+    /// The marker is written in the same batch as every appended record, so
+    /// normally the scan below stops right after the marker. When the marker is
+    /// missing or stale, the scan walks the remaining records and the repaired
+    /// position is written back.
     fn seek_last(&mut self) {
-        let mut iter = self.table.prefix_iterator(TOPIC_KEY_PREFIX);
+        let mut last = self.last_insert();
+        let start_from = match &last {
+            Some(last) => last.to_string(),
+            None => TOPIC_KEY_PREFIX.to_string(),
+        };
+
+        let mut repaired = false;
+        let mut iter = self.table.prefix_iterator(start_from);
         loop {
             if !iter.valid() {
                 break;
@@ -43,47 +89,427 @@ where
             }
 
             let record = SeqRecord::from(item);
-            if record.is_valid() {
-                self.next_insert = record.key;
+            if !record.is_valid() {
+                break;
+            }
+
+            let is_marker = match &last {
+                Some(last) => last.to_string() == record.key.to_string(),
+                None => false,
+            };
+            if !is_marker {
+                last = Some(record.key);
+                repaired = true;
             }
 
             iter.next();
         }
 
-        self.next_insert = self.next_insert.next_id();
+        self.next_insert = match last {
+            Some(last) => {
+                if repaired {
+                    // NOTE: Failing to persist the repaired marker only means the next open scans again.
+                    let _ = self.table.insert(TOPIC_LAST_INSERT_KEY, last.to_string());
+                }
+                last.next_id()
+            }
+            None => self.next_insert.next_id(),
+        };
     }
 
     pub fn append(&mut self, value: &Record) -> Result<SeqRecord> {
-        let key = self.next_insert.to_string();
+        let mut records = self.append_batch(std::slice::from_ref(value))?;
+        Ok(records.remove(0))
+    }
+
+    /// Appends all values in a single write batch and returns the assigned records.
+    pub fn append_batch(&mut self, values: &[Record]) -> Result<Vec<SeqRecord>> {
+        self.reclaim();
+        let mut next_insert = stamped(&self.next_insert);
+        let mut records = Vec::with_capacity(values.len());
+
+        for value in values {
+            records.push(SeqRecord::new(next_insert.clone(), value.clone()));
+            next_insert = next_insert.next_id();
+        }
+
+        self.write_records(&records)?;
+        Ok(records)
+    }
+
+    /// Rewinds `next_insert` over keys which transactions reserved but did not commit.
+    ///
+    /// Only the latest reservations are given back, keys assigned after them
+    /// are never reused and a gap stays behind.
+    pub(crate) fn reclaim(&mut self) {
+        let mut discarded = self
+            .discarded
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        while let Some(index) = discarded
+            .iter()
+            .position(|(_, to)| to.to_u128() == self.next_insert.to_u128())
+        {
+            let (from, _) = discarded.swap_remove(index);
+            self.next_insert = from;
+        }
+        discarded.retain(|(_, to)| to.to_u128() < self.next_insert.to_u128());
+    }
+
+    pub(crate) fn discarded(&self) -> &Discarded {
+        &self.discarded
+    }
+
+    /// Returns a buffered producer which groups appends into write batches.
+    pub fn producer(&'_ mut self) -> WriteBuffer<'_, T> {
+        WriteBuffer::new(self)
+    }
+
+    /// Writes records with already assigned keys together with the last insert marker.
+    pub(crate) fn write_records(&mut self, records: &[SeqRecord]) -> Result<()> {
+        let last = match records.last() {
+            Some(last) => last.key.clone(),
+            None => return Ok(()),
+        };
+
+        let mut batch = Batch::default();
+        self.stage_records(&mut batch, records);
 
-        self.table
-            .insert(key, value)
-            .map_err(|err| Error::DbError(err))?;
+        self.table.apply(batch)?;
 
-        self.next_insert = self.next_insert.next_id();
-        Ok(SeqRecord::new(self.next_insert.clone(), value.clone()))
+        self.next_insert = last.next_id();
+        self.notifier.notify();
+        Ok(())
+    }
+
+    /// Adds records with already assigned keys, the last insert marker and the time
+    /// index entry to the batch.
+    pub(crate) fn stage_records(&self, batch: &mut Batch, records: &[SeqRecord]) {
+        let (first, last) = match (records.first(), records.last()) {
+            (Some(first), Some(last)) => (first.key.to_string(), last.key.to_string()),
+            _ => return,
+        };
+
+        for record in records {
+            batch.put(&self.table, record.key.to_string(), &record.value);
+        }
+        batch.put(&self.table, TOPIC_LAST_INSERT_KEY, last);
+        batch.put(
+            &self.table,
+            time_index_key(epoch_ns(), Some(&first)),
+            first.as_bytes(),
+        );
+    }
+
+    /// Retention policy configured for the topic.
+    pub fn retention(&self) -> Retention {
+        let mut policy = Retention::default();
+        T::retention(&mut policy);
+        policy
+    }
+
+    /// Deletes records outside of the topic retention policy.
+    pub fn enforce_retention(&self) -> Result<Option<ByteCounter>> {
+        retention::enforce(&self.table, &self.retention())
+    }
+
+    /// Enforces the topic retention policy on a background thread every `interval`.
+    pub fn spawn_retention(&self, interval: Duration) -> RetentionTask
+    where
+        T: Send + 'static,
+    {
+        RetentionTask::spawn(self.table.clone_handle(), self.retention(), interval)
+    }
+
+    /// Point in time view of the topic, see `DatabaseSnapshot::table`.
+    pub fn snapshot(&'_ self) -> Result<DatabaseSnapshot<'_>> {
+        self.table.snapshot()
     }
 
     pub fn window(&'_ self, name: &str, batch_size: usize) -> IteratorBatch<'_, T> {
         IteratorBatch::new(Box::new(self), name, batch_size)
     }
 
+    /// Named batch iterator which only advances its checkpoint on explicit commit
+    /// when `CommitMode::Manual` is used.
+    pub fn window_with_commit(
+        &'_ self,
+        name: &str,
+        batch_size: usize,
+        commit_mode: CommitMode,
+    ) -> IteratorBatch<'_, T> {
+        IteratorBatch::with_commit_mode(Box::new(self), name, batch_size, commit_mode)
+    }
+
+    /// Lists and manages the named iterators of the topic.
+    pub fn consumers(&'_ self) -> Consumers<'_, T> {
+        Consumers::new(self)
+    }
+
     pub fn iter(&'_ self) -> IteratorSingle<'_, T> {
         IteratorSingle::new(Box::new(self))
     }
+
+    /// Iterates over all records from the newest one to the oldest one.
+    pub fn iter_rev(&'_ self) -> IteratorRange<'_> {
+        IteratorRange::new(self, None, None, true)
+    }
+
+    /// Iterates over records with keys in `[from, to)`, either bound can be omitted.
+    pub fn range(
+        &'_ self,
+        from: Option<&ByteCounter>,
+        to: Option<&ByteCounter>,
+    ) -> IteratorRange<'_> {
+        IteratorRange::new(self, from, to, false)
+    }
+
+    /// Iterates over records with keys in `[from, to)` from the newest one.
+    pub fn range_rev(
+        &'_ self,
+        from: Option<&ByteCounter>,
+        to: Option<&ByteCounter>,
+    ) -> IteratorRange<'_> {
+        IteratorRange::new(self, from, to, true)
+    }
+
+    /// Returns up to `count` newest records in append order.
+    pub fn last_n(&self, count: usize) -> Result<Vec<SeqRecord>> {
+        let mut records = self.iter_rev().take(count).collect::<Result<Vec<_>>>()?;
+        records.reverse();
+        Ok(records)
+    }
+
+    /// Iterator which starts at the record with the given key.
+    pub fn iter_from(&'_ self, key: &ByteCounter) -> IteratorSingle<'_, T> {
+        IteratorSingle::from_key(Box::new(self), key)
+    }
+
+    /// Iterator which starts at the first record appended at or after `epoch_ns`.
+    pub fn iter_since(&'_ self, epoch_ns: u128) -> IteratorSingle<'_, T> {
+        IteratorSingle::from_time(Box::new(self), epoch_ns)
+    }
+
+    /// Named batch iterator which starts at the record with the given key instead
+    /// of its checkpoint.
+    pub fn window_from(
+        &'_ self,
+        name: &str,
+        batch_size: usize,
+        key: &ByteCounter,
+    ) -> IteratorBatch<'_, T> {
+        IteratorBatch::from_key(Box::new(self), name, batch_size, CommitMode::Auto, key)
+    }
+
+    /// Named batch iterator which starts at the first record appended at or after
+    /// `epoch_ns` instead of its checkpoint.
+    pub fn window_since(
+        &'_ self,
+        name: &str,
+        batch_size: usize,
+        epoch_ns: u128,
+    ) -> IteratorBatch<'_, T> {
+        IteratorBatch::from_time(Box::new(self), name, batch_size, CommitMode::Auto, epoch_ns)
+    }
+
+    /// Async stream of all records which waits for new appends once it reaches the end.
+    pub fn stream(&'_ self) -> TopicStream<'_, T> {
+        TopicStream::new(self, None)
+    }
+
+    /// Async stream of records appended after the record with the given key.
+    pub fn stream_after(&'_ self, key: &ByteCounter) -> TopicStream<'_, T> {
+        TopicStream::new(self, Some(key.to_string().into_bytes()))
+    }
+}
+
+impl<T> TopicImpl<T>
+where
+    T: Table,
+{
+    /// Returns the key of the last appended record as persisted in the topic.
+    pub fn last_insert(&self) -> Option<ByteCounter> {
+        match self.table.get(TOPIC_LAST_INSERT_KEY) {
+            Ok(Some(value)) => {
+                let value = String::from_utf8_lossy(value.as_ref()).to_string();
+                let last = ByteCounter::from(&value);
+                if last.valid {
+                    Some(last)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Raw iterator positioned at the first record after `key` or at the first record
+    /// of the topic.
+    pub(crate) fn iter_after(&'_ self, key: Option<&[u8]>) -> rocksdb::DBRawIterator<'_> {
+        match key {
+            Some(key) => {
+                let mut iter = self.table.prefix_iterator(key);
+                if iter.key() == Some(key) {
+                    iter.next();
+                }
+                iter
+            }
+            None => self.table.prefix_iterator(TOPIC_KEY_PREFIX),
+        }
+    }
+
+    /// Raw iterator positioned at the record with the given key or the first one after it.
+    pub(crate) fn iter_at(&'_ self, key: &[u8]) -> rocksdb::DBRawIterator<'_> {
+        self.table.prefix_iterator(key)
+    }
+
+    /// Returns the key to seek to for the record with the counter of `key`.
+    ///
+    /// Record keys lead with the second their counter was created, so a counter
+    /// rebuilt with another timestamp does not sort next to its record. Counters
+    /// and seconds increase together, the second of the record is found by a
+    /// binary search over the seconds of the topic. The result is the stored key
+    /// of the first record at or after the counter, or the key of the counter
+    /// itself when no such record was appended yet.
+    pub fn seek_key(&self, key: &ByteCounter) -> Vec<u8> {
+        let own = record_key(key.timestamp.value(), key);
+
+        // NOTE: Counters which kept their timestamp land on their record directly.
+        match self.record_at(own.as_bytes()) {
+            Some((found, stored)) if found.id == key.id => return stored,
+            _ => {}
+        }
+
+        let first = match self.record_at(TOPIC_KEY_PREFIX.as_bytes()) {
+            Some((first, _)) if first.id < key.id => first,
+            Some((_, stored)) => return stored,
+            None => return own.into_bytes(),
+        };
+
+        let mut iter = self.table.raw_iterator();
+        iter.seek_for_prev(TOPIC_KEY_END);
+        let last = SeqRecord::from(iter.item());
+        if !last.is_valid() || last.key.id < key.id {
+            return own.into_bytes();
+        }
+
+        // NOTE: Finds the first second whose records all come after the counter.
+        let mut low = first.timestamp.value();
+        let mut high = last.key.timestamp.value() + 1;
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let prefix = format!("{}:{}:", TOPIC_KEY_PREFIX, mid);
+            match self.record_at(prefix.as_bytes()) {
+                Some((found, _)) if found.id <= key.id => low = mid + 1,
+                _ => high = mid,
+            }
+        }
+
+        match self.record_at(record_key(low - 1, key).as_bytes()) {
+            Some((_, stored)) => stored,
+            None => own.into_bytes(),
+        }
+    }
+
+    /// Returns the key to store as checkpoint for the record with the counter of `key`.
+    ///
+    /// Checkpoints are compared with stored record keys, so the counter is
+    /// normalized through `seek_key`. A counter without a record takes the second
+    /// of the next record, it still sorts right before that record.
+    pub(crate) fn checkpoint_key(&self, key: &ByteCounter) -> String {
+        let stored = String::from_utf8_lossy(&self.seek_key(key)).to_string();
+        let found = ByteCounter::from(&stored);
+        if found.valid && found.id != key.id {
+            return record_key(found.timestamp.value(), key);
+        }
+        stored
+    }
+
+    /// Returns the counter and stored key of the first record at or after `from`.
+    fn record_at(&self, from: &[u8]) -> Option<(ByteCounter, Vec<u8>)> {
+        let mut iter = self.table.raw_iterator();
+        iter.seek(from);
+
+        let stored = iter.key()?.to_vec();
+        let record = SeqRecord::from(iter.item());
+        if record.is_valid() {
+            Some((record.key, stored))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the key of the first record appended at or after `epoch_ns`.
+    ///
+    /// Append times come from the time index which is written with every batch,
+    /// `None` means nothing was appended since then. Records of a batch share the
+    /// time of the batch.
+    pub fn seek_time(&self, epoch_ns: u128) -> Option<ByteCounter> {
+        let prefix = format!("{}:", TOPIC_TIME_INDEX_KEY_PREFIX);
+        let iter = self.table.prefix_iterator(time_index_key(epoch_ns, None));
+        if !iter.valid() {
+            return None;
+        }
+
+        match iter.item() {
+            Some((key, value)) if key.starts_with(prefix.as_bytes()) => {
+                let value = String::from_utf8_lossy(value).to_string();
+                let first = ByteCounter::from(&value);
+                if first.valid {
+                    Some(first)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Copy of the counter which carries the current second.
+///
+/// `next_insert` is created by the previous append, a record key has to carry
+/// the second of its own append so that retention ages it correctly.
+pub(crate) fn stamped(counter: &ByteCounter) -> ByteCounter {
+    let mut counter = counter.clone();
+    counter.timestamp = Timestamp::new();
+    counter
+}
+
+/// Key of the record with the counter of `key` created in the given second.
+fn record_key(second: u64, key: &ByteCounter) -> String {
+    let id: String = key.id.iter().map(|byte| format!("{:03}", byte)).collect();
+    format!("{}:{}:{}", TOPIC_KEY_PREFIX, second, id)
+}
+
+/// Key of a time index entry, the record key keeps entries of batches written
+/// within the same nanosecond apart.
+fn time_index_key(epoch_ns: u128, first: Option<&str>) -> String {
+    match first {
+        Some(first) => format!("{}:{:039}:{}", TOPIC_TIME_INDEX_KEY_PREFIX, epoch_ns, first),
+        None => format!("{}:{:039}", TOPIC_TIME_INDEX_KEY_PREFIX, epoch_ns),
+    }
 }
 
 #[cfg(test)]
 mod tests {
 
-    use std::fs;
+    use std::{fs, thread, time::Duration};
+
+    use byte_counter::{counter::ByteCounter, timestamp::Timestamp};
 
     use crate::{
-        builder::StructDB, caches::Caches, database::Database, iterator_batch::BatchIterator,
-        record::SeqRecord, serialization::BinCode, table::Table,
+        builder::StructDB,
+        caches::Caches,
+        database::Database,
+        iterator_batch::{BatchIterator, CommitMode},
+        record::SeqRecord,
+        serialization::BinCode,
+        table::Table,
+        timestamp::epoch_ns,
     };
 
-    use super::Topic;
+    use super::{Topic, TOPIC_KEY_PREFIX, TOPIC_LAST_INSERT_KEY};
 
     struct MyTopic;
 
@@ -93,6 +519,93 @@ mod tests {
 
     impl Topic for MyTopic {}
 
+    #[test]
+    fn test_topic_seek() {
+        let _ = fs::remove_dir_all("test_topic_seek.db");
+        let db = StructDB::builder("test_topic_seek.db", Caches::default())
+            .with_struct::<MyTopic>()
+            .build()
+            .unwrap();
+
+        let mut topic = db.make_topic::<MyTopic>();
+        let before = epoch_ns();
+        let first = topic.append_batch(&vec![vec![1; 8]; 5]).unwrap();
+
+        thread::sleep(Duration::from_millis(5));
+        let since = epoch_ns();
+        let second = topic.append_batch(&vec![vec![2; 8]; 5]).unwrap();
+
+        let keys: Vec<u128> = topic
+            .iter_from(&first[3].key)
+            .map(|r| r.key.to_u128())
+            .collect();
+        assert_eq!(keys, vec![4, 5, 6, 7, 8, 9, 10]);
+
+        assert_eq!(topic.seek_time(before).unwrap().to_u128(), 1);
+        assert_eq!(topic.seek_time(since), Some(second[0].key.clone()));
+        assert_eq!(topic.iter_since(since).count(), 5);
+        assert!(topic.seek_time(epoch_ns()).is_none());
+        assert_eq!(topic.iter_since(epoch_ns()).count(), 0);
+
+        let batch = topic.window_since("iter1", 3, since).next().unwrap();
+        assert_eq!(batch[0].key.to_u128(), 6);
+        let batch = topic.window("iter1", 3).next().unwrap();
+        assert_eq!(batch[0].key.to_u128(), 9);
+
+        let batch = topic.window_from("iter1", 3, &first[1].key).next().unwrap();
+        assert_eq!(batch[0].key.to_u128(), 2);
+
+        // NOTE: Counters rebuilt with another timestamp still seek by their id.
+        let mut rebuilt = ByteCounter::new();
+        rebuilt.id = first[3].key.id;
+        rebuilt.timestamp = Timestamp::from("1000000000");
+        assert_eq!(topic.iter_from(&rebuilt).next().unwrap().key, first[3].key);
+        assert_eq!(topic.range(None, Some(&rebuilt)).count(), 3);
+
+        // NOTE: Index entries are not records and are skipped by readers.
+        assert_eq!(topic.iter().count(), 10);
+    }
+
+    #[test]
+    fn test_topic_range() {
+        let _ = fs::remove_dir_all("test_topic_range.db");
+        let db = StructDB::builder("test_topic_range.db", Caches::default())
+            .with_struct::<MyTopic>()
+            .build()
+            .unwrap();
+
+        let mut topic = db.make_topic::<MyTopic>();
+        let records = topic.append_batch(&vec![vec![1; 8]; 10]).unwrap();
+        topic.window("iter1", 2).next().unwrap();
+
+        let keys: Vec<u128> = topic.iter_rev().map(|r| r.unwrap().key.to_u128()).collect();
+        assert_eq!(keys, (1..=10).rev().collect::<Vec<u128>>());
+
+        let keys: Vec<u128> = topic
+            .last_n(3)
+            .unwrap()
+            .iter()
+            .map(|r| r.key.to_u128())
+            .collect();
+        assert_eq!(keys, vec![8, 9, 10]);
+        assert_eq!(topic.last_n(20).unwrap().len(), 10);
+
+        let (from, to) = (&records[2].key, &records[5].key);
+        let keys: Vec<u128> = topic
+            .range(Some(from), Some(to))
+            .map(|r| r.unwrap().key.to_u128())
+            .collect();
+        assert_eq!(keys, vec![3, 4, 5]);
+        let keys: Vec<u128> = topic
+            .range_rev(Some(from), Some(to))
+            .map(|r| r.unwrap().key.to_u128())
+            .collect();
+        assert_eq!(keys, vec![5, 4, 3]);
+
+        assert_eq!(topic.range(None, Some(from)).count(), 2);
+        assert_eq!(topic.range(Some(to), None).count(), 5);
+    }
+
     #[test]
     fn test_topic_write() {
         let _ = fs::remove_dir_all("test_topic_write.db");
@@ -243,6 +756,11 @@ mod tests {
                 if item.is_none() {
                     break;
                 }
+                // NOTE: Last insert marker and time index sort before the records.
+                if !iter.key().unwrap().starts_with(TOPIC_KEY_PREFIX.as_bytes()) {
+                    iter.next();
+                    continue;
+                }
                 let record = SeqRecord::from(item);
                 assert!(record.is_valid());
 
@@ -340,4 +858,120 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_topic_last_insert_recovery() {
+        let _ = fs::remove_dir_all("test_topic_last_insert_recovery.db");
+        let db = StructDB::builder("test_topic_last_insert_recovery.db", Caches::default())
+            .with_struct::<MyTopic>()
+            .build()
+            .unwrap();
+
+        let mut topic = db.make_topic::<MyTopic>();
+        for i in 0..10 {
+            let value = format!("topic-value-{}", i).to_bytes().unwrap();
+            topic.append(&value).unwrap();
+        }
+        let expected = topic.next_insert.to_u128();
+        assert_eq!(topic.last_insert().unwrap().next_id().to_u128(), expected);
+
+        {
+            let reopened = db.make_topic::<MyTopic>();
+            assert_eq!(reopened.next_insert.to_u128(), expected);
+        }
+
+        {
+            // NOTE: Missing marker falls back to a full scan and is repaired.
+            topic.table.remove(TOPIC_LAST_INSERT_KEY).unwrap();
+            let reopened = db.make_topic::<MyTopic>();
+            assert_eq!(reopened.next_insert.to_u128(), expected);
+            assert!(reopened.last_insert().is_some());
+        }
+
+        {
+            // NOTE: Stale marker is moved forward to the last written record.
+            let stale = topic.last_insert().unwrap();
+            let value = "topic-value-10".to_bytes().unwrap();
+            topic.append(&value).unwrap();
+            topic
+                .table
+                .insert(TOPIC_LAST_INSERT_KEY, stale.to_string())
+                .unwrap();
+
+            let reopened = db.make_topic::<MyTopic>();
+            assert_eq!(reopened.next_insert.to_u128(), topic.next_insert.to_u128());
+        }
+    }
+
+    #[test]
+    fn test_topic_append_batch() {
+        let _ = fs::remove_dir_all("test_topic_append_batch.db");
+        let db = StructDB::builder("test_topic_append_batch.db", Caches::default())
+            .with_struct::<MyTopic>()
+            .build()
+            .unwrap();
+
+        let mut topic = db.make_topic::<MyTopic>();
+        let first = topic.append(&"topic-value-0".to_bytes().unwrap()).unwrap();
+        assert_eq!(first.key.to_u128(), 1);
+
+        let values: Vec<_> = (1..10)
+            .map(|i| format!("topic-value-{}", i).to_bytes().unwrap())
+            .collect();
+        let records = topic.append_batch(&values).unwrap();
+        assert_eq!(records.len(), 9);
+        assert_eq!(
+            topic.last_insert().unwrap().to_string(),
+            records[8].key.to_string()
+        );
+
+        let received: Vec<SeqRecord> = topic.iter().collect();
+        assert_eq!(received.len(), 10);
+        for (record, expected) in received[1..].iter().zip(records.iter()) {
+            assert_eq!(record.key.to_string(), expected.key.to_string());
+            assert_eq!(record.value, expected.value);
+        }
+    }
+
+    #[test]
+    fn test_topic_window_manual_commit() {
+        let _ = fs::remove_dir_all("test_topic_window_manual_commit.db");
+        let db = StructDB::builder("test_topic_window_manual_commit.db", Caches::default())
+            .with_struct::<MyTopic>()
+            .build()
+            .unwrap();
+
+        let mut topic = db.make_topic::<MyTopic>();
+        for i in 0..30 {
+            let value = format!("topic-value-{}", i).to_bytes().unwrap();
+            topic.append(&value).unwrap();
+        }
+
+        {
+            let mut iter = topic.window_with_commit("iter1", 10, CommitMode::Manual);
+            let batch = iter.next().unwrap();
+            assert_eq!(batch.len(), 10);
+            assert_eq!(iter.tail_distance(), 30);
+        }
+
+        {
+            // NOTE: Uncommitted batch is delivered again.
+            let mut iter = topic.window_with_commit("iter1", 10, CommitMode::Manual);
+            let batch = iter.next().unwrap();
+            assert_eq!(batch[0].key.to_u128(), 1);
+            iter.ack().unwrap();
+            assert_eq!(iter.tail_distance(), 20);
+
+            let batch = iter.next().unwrap();
+            assert_eq!(batch[0].key.to_u128(), 11);
+            iter.commit(&batch[4].key).unwrap();
+            assert_eq!(iter.tail_distance(), 15);
+        }
+
+        {
+            let mut iter = topic.window_with_commit("iter1", 10, CommitMode::Manual);
+            let batch = iter.next().unwrap();
+            assert_eq!(batch[0].key.to_u128(), 16);
+        }
+    }
 }
@@ -0,0 +1,563 @@
+use std::{sync::Arc, time::Duration};
+
+use byte_counter::counter::ByteCounter;
+
+use crate::{
+    batch::Batch,
+    errors::{Error, Result},
+    follow::Notifier,
+    locks::KeyLocks,
+    record::{Record, SeqRecord},
+    table::{Table, TableImpl},
+    topic::{stamped, Discarded, Topic, TopicImpl},
+    typed_table::{TypedTable, TypedTableImpl},
+    watch::Watchers,
+};
+
+/// How long a transaction waits for a key locked by another one before it
+/// fails with `Error::TransactionConflict`.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Concurrency control of transactions, set with `Builder::with_transaction_mode`.
+///
+/// Both modes fail a commit if a key it writes or read with `get_for_update`
+/// was written by anyone else since the transaction started, the first
+/// writer wins. They differ in how transactions wait for each other.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionMode {
+    /// Transactions never wait, conflicts are detected on commit.
+    #[default]
+    Optimistic,
+    /// Keys read with `get_for_update` and written keys are locked for the
+    /// transaction, other transactions wait for them until it ends.
+    Pessimistic,
+}
+
+/// Key read with `get_for_update` which must not be written after `since`.
+struct ReadCheck {
+    cf_name: String,
+    key: Vec<u8>,
+    since: u64,
+}
+
+/// Puts, deletes and topic appends across several tables which are written
+/// atomically in a single write batch on `commit`, while their keys are locked.
+///
+/// Nothing is visible to readers before the commit, a transaction which is
+/// dropped without commit is discarded. Appends advance `next_insert` of the
+/// topic right away, the topic takes the keys of a discarded transaction back
+/// on its next append unless it assigned later keys meanwhile.
+///
+/// Reads see the snapshot taken when the transaction started together with its
+/// own staged writes. The commit fails with `Error::TransactionConflict` if a
+/// written key or a key read with `get_for_update` was written since the
+/// snapshot, through any handle of the same `StructDB`, see `TransactionMode`.
+/// Locks of a pessimistic transaction only make other transactions wait, the
+/// same thread may keep writing through table handles while it runs.
+///
+/// The batch is written with the write options of all participating tables,
+/// applied in the order the tables were first used. Options set by a later
+/// table override the same options of an earlier one.
+pub struct Transaction<'a> {
+    db: &'a rocksdb::DB,
+    locks: &'a KeyLocks,
+    watchers: &'a Watchers,
+    mode: TransactionMode,
+    owner: u64,
+    start: u64,
+    snapshot: rocksdb::SnapshotWithThreadMode<'a, rocksdb::DB>,
+    batch: Batch,
+    reads: Vec<ReadCheck>,
+    notifiers: Vec<Arc<Notifier>>,
+    appends: Vec<(Discarded, ByteCounter, ByteCounter)>,
+    committed: bool,
+    tables: Vec<String>,
+    write_config: rocksdb::WriteOptions,
+}
+
+impl<'a> Transaction<'a> {
+    pub fn new(
+        db: &'a rocksdb::DB,
+        locks: &'a KeyLocks,
+        watchers: &'a Watchers,
+        mode: TransactionMode,
+    ) -> Self {
+        // NOTE: Version is taken before the snapshot, writes in between count as conflicts.
+        let start = locks.version();
+        Self {
+            db,
+            locks,
+            watchers,
+            mode,
+            owner: locks.new_owner(),
+            start,
+            snapshot: db.snapshot(),
+            batch: Batch::default(),
+            reads: vec![],
+            notifiers: vec![],
+            appends: vec![],
+            committed: false,
+            tables: vec![],
+            write_config: Default::default(),
+        }
+    }
+
+    pub fn mode(&self) -> TransactionMode {
+        self.mode
+    }
+
+    /// Applies the write options of the table once it takes part in the transaction.
+    fn join<T: Table>(&mut self, table: &TableImpl<T>) {
+        if !self.tables.contains(&table.name) {
+            T::write_options(&mut self.write_config);
+            self.tables.push(table.name.clone());
+        }
+    }
+
+    /// Reads the key at the snapshot of the transaction, including its own writes.
+    pub fn get<T: Table>(&self, table: &TableImpl<T>, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if let Some(staged) = self.batch.staged(table, key) {
+            return Ok(staged.map(|value| value.to_vec()));
+        }
+        self.snapshot.get_cf(&table.cf(), key).map_err(Into::into)
+    }
+
+    /// Reads the key and makes the commit fail if the key is written concurrently.
+    ///
+    /// In optimistic mode the key is read at the snapshot and must not be written
+    /// after it. In pessimistic mode the key is locked until the transaction ends
+    /// and the latest value is returned, it must not be written after that.
+    pub fn get_for_update<T: Table>(
+        &mut self,
+        table: &TableImpl<T>,
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        if let Some(staged) = self.batch.staged(table, key) {
+            return Ok(staged.map(|value| value.to_vec()));
+        }
+
+        let (since, value) = match self.mode {
+            TransactionMode::Optimistic => (self.start, self.snapshot.get_cf(&table.cf(), key)?),
+            TransactionMode::Pessimistic => {
+                self.lock_row(&table.name, key)?;
+                let since = self.locks.version();
+                (since, table.get(key)?.map(|value| value.to_vec()))
+            }
+        };
+        self.reads.push(ReadCheck {
+            cf_name: table.name.clone(),
+            key: key.to_vec(),
+            since,
+        });
+        Ok(value)
+    }
+
+    pub fn get_typed<T: TypedTable>(
+        &self,
+        table: &TypedTableImpl<T>,
+        key: &T::Key,
+    ) -> Result<Option<T::Value>> {
+        match self.get(&table.table, &T::encode_key(key)?)? {
+            Some(value) => T::decode_value(&value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_for_update_typed<T: TypedTable>(
+        &mut self,
+        table: &TypedTableImpl<T>,
+        key: &T::Key,
+    ) -> Result<Option<T::Value>> {
+        match self.get_for_update(&table.table, &T::encode_key(key)?)? {
+            Some(value) => T::decode_value(&value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Stages a raw write, indexes of a typed table are maintained by `put_typed` only.
+    pub fn put<T, K, V>(&mut self, table: &TableImpl<T>, key: K, value: V)
+    where
+        T: Table,
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        self.join(table);
+        self.batch.put(table, key, value);
+    }
+    pub fn delete<T, K>(&mut self, table: &TableImpl<T>, key: K)
+    where
+        T: Table,
+        K: AsRef<[u8]>,
+    {
+        self.join(table);
+        self.batch.delete(table, key);
+    }
+
+    pub fn put_typed<T: TypedTable>(
+        &mut self,
+        table: &TypedTableImpl<T>,
+        key: &T::Key,
+        value: &T::Value,
+    ) -> Result<()> {
+        self.join(&table.table);
+        table.stage_insert(&mut self.batch, key, value)
+    }
+
+    pub fn delete_typed<T: TypedTable>(
+        &mut self,
+        table: &TypedTableImpl<T>,
+        key: &T::Key,
+    ) -> Result<()> {
+        self.join(&table.table);
+        table.stage_remove(&mut self.batch, key)
+    }
+
+    /// Stages a topic record and returns it with its assigned key.
+    pub fn append<T: Topic>(&mut self, topic: &mut TopicImpl<T>, value: &Record) -> SeqRecord {
+        self.join(&topic.table);
+        topic.reclaim();
+        let record = SeqRecord::new(stamped(&topic.next_insert), value.clone());
+        topic.stage_records(&mut self.batch, std::slice::from_ref(&record));
+        topic.next_insert = topic.next_insert.next_id();
+
+        // NOTE: Consecutive keys form one range, keys assigned in between by the topic split it.
+        match self.appends.last_mut() {
+            Some((discarded, _, to))
+                if Arc::ptr_eq(discarded, topic.discarded())
+                    && to.to_u128() == record.key.to_u128() =>
+            {
+                *to = topic.next_insert.clone();
+            }
+            _ => self.appends.push((
+                topic.discarded().clone(),
+                record.key.clone(),
+                topic.next_insert.clone(),
+            )),
+        }
+
+        if !self
+            .notifiers
+            .iter()
+            .any(|notifier| Arc::ptr_eq(notifier, &topic.notifier))
+        {
+            self.notifiers.push(topic.notifier.clone());
+        }
+
+        record
+    }
+
+    /// Number of staged operations.
+    pub fn len(&self) -> usize {
+        self.batch.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batch.is_empty()
+    }
+
+    /// Writes all staged operations atomically.
+    ///
+    /// Fails with `Error::TransactionConflict` if a written key or a key read
+    /// with `get_for_update` was written since, or if a key stays locked by
+    /// another transaction for longer than the lock timeout.
+    pub fn commit(mut self) -> Result<()> {
+        if self.mode == TransactionMode::Pessimistic {
+            for (cf_name, key) in self.batch.written() {
+                self.lock_row(cf_name, key)?;
+            }
+        }
+
+        let locks = self.locks;
+        let guards = locks.lock_all(
+            self.batch.keys().chain(
+                self.reads
+                    .iter()
+                    .map(|read| (read.cf_name.as_str(), read.key.as_slice())),
+            ),
+        );
+
+        for read in &self.reads {
+            if locks.written_since(&read.cf_name, &read.key, read.since) {
+                return Err(Error::TransactionConflict(read.cf_name.clone()));
+            }
+        }
+        for (cf_name, key) in self.batch.written() {
+            let tracked = self
+                .reads
+                .iter()
+                .any(|read| read.cf_name == cf_name && read.key == key);
+            if !tracked && locks.written_since(cf_name, key, self.start) {
+                return Err(Error::TransactionConflict(cf_name.to_string()));
+            }
+        }
+
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+
+        self.batch.verify(self.db)?;
+        let (batch, writes) = std::mem::take(&mut self.batch).into_parts();
+        self.db.write_opt(batch, &self.write_config)?;
+        for write in &writes {
+            locks.record_write(&write.cf_name, &write.key);
+        }
+        drop(guards);
+        self.committed = true;
+
+        self.watchers.notify_all(&writes);
+        for notifier in &self.notifiers {
+            notifier.notify();
+        }
+        Ok(())
+    }
+
+    /// Locks the key until the transaction ends, unless it is locked by it already.
+    fn lock_row(&self, cf_name: &str, key: &[u8]) -> Result<()> {
+        if self.locks.lock_row(self.owner, cf_name, key, LOCK_TIMEOUT) {
+            Ok(())
+        } else {
+            Err(Error::TransactionConflict(
+                "key locked by another transaction".to_string(),
+            ))
+        }
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        self.locks.unlock_rows(self.owner);
+
+        if !self.committed {
+            for (discarded, from, to) in self.appends.drain(..) {
+                discarded
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .push((from, to));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        builder::StructDB, caches::Caches, errors::Error, table::Table, topic::Topic,
+        typed_table::TypedTable,
+    };
+
+    use super::TransactionMode;
+
+    struct Orders;
+
+    impl Table for Orders {
+        const NAME: &'static str = "orders";
+    }
+
+    impl TypedTable for Orders {
+        type Key = u64;
+        type Value = String;
+    }
+
+    struct OrdersByCustomer;
+
+    impl Table for OrdersByCustomer {
+        const NAME: &'static str = "orders-by-customer";
+    }
+
+    struct Outbox;
+
+    impl Table for Outbox {
+        const NAME: &'static str = "outbox";
+    }
+
+    impl Topic for Outbox {}
+
+    #[test]
+    fn test_transaction() {
+        let _ = fs::remove_dir_all("test_transaction.db");
+        let db = StructDB::builder("test_transaction.db", Caches::default())
+            .with_struct::<Orders>()
+            .with_struct::<OrdersByCustomer>()
+            .with_struct::<Outbox>()
+            .build()
+            .unwrap();
+
+        let orders = db.make_typed_table::<Orders>();
+        let index = db.make_table::<OrdersByCustomer>();
+        let mut outbox = db.make_topic::<Outbox>();
+
+        let record = db
+            .transaction(|txn| {
+                txn.put_typed(&orders, &1, &"order-1".to_string())?;
+                txn.put(&index, "alice:1", b"");
+                Ok(txn.append(&mut outbox, &b"created".to_vec()))
+            })
+            .unwrap();
+        assert_eq!(record.key.to_u128(), 1);
+        assert_eq!(orders.get(&1).unwrap(), Some("order-1".to_string()));
+        assert!(index.contains_key("alice:1").unwrap());
+        assert_eq!(outbox.iter().count(), 1);
+
+        let result: Result<(), Error> = db.transaction(|txn| {
+            txn.delete_typed(&orders, &1)?;
+            txn.delete(&index, "alice:1");
+            txn.append(&mut outbox, &b"deleted".to_vec());
+            Err(Error::InvalidDbVersion)
+        });
+        assert!(result.is_err());
+        assert!(orders.get(&1).unwrap().is_some());
+        assert!(index.contains_key("alice:1").unwrap());
+        assert_eq!(outbox.iter().count(), 1);
+
+        // NOTE: Keys of the discarded transaction are given back, the sequence has no gap.
+        let record = outbox.append(&b"updated".to_vec()).unwrap();
+        assert_eq!(record.key.to_u128(), 2);
+
+        let mut attempts = 0;
+        let record = db
+            .transaction_with_retry(3, |txn| {
+                attempts += 1;
+                let record = txn.append(&mut outbox, &b"shipped".to_vec());
+                if attempts == 1 {
+                    return Err(Error::TransactionConflict("retry".to_string()));
+                }
+                Ok(record)
+            })
+            .unwrap();
+        assert_eq!(attempts, 2);
+        assert_eq!(record.key.to_u128(), 3);
+        assert_eq!(outbox.iter().count(), 3);
+    }
+
+    struct Balances;
+
+    impl Table for Balances {
+        const NAME: &'static str = "balances";
+    }
+
+    impl TypedTable for Balances {
+        type Key = String;
+        type Value = i64;
+    }
+
+    #[test]
+    fn test_optimistic_transaction_conflict() {
+        let _ = fs::remove_dir_all("test_optimistic_transaction_conflict.db");
+        let db = StructDB::builder("test_optimistic_transaction_conflict.db", Caches::default())
+            .with_struct::<Balances>()
+            .build()
+            .unwrap();
+        assert_eq!(db.transaction_mode(), TransactionMode::Optimistic);
+
+        let balances = db.make_typed_table::<Balances>();
+        let key = "alice".to_string();
+        balances.insert(&key, &100).unwrap();
+
+        let result = db.transaction(|txn| {
+            let balance = txn.get_for_update_typed(&balances, &key)?.unwrap();
+            db.transaction(|other| other.put_typed(&balances, &key, &0))?;
+
+            // NOTE: Reads see the snapshot of the transaction, not the concurrent write.
+            assert_eq!(txn.get_typed(&balances, &key)?, Some(100));
+            txn.put_typed(&balances, &key, &(balance + 10))
+        });
+        assert!(matches!(result, Err(Error::TransactionConflict(_))));
+        assert!(result.unwrap_err().is_conflict());
+        assert_eq!(balances.get(&key).unwrap(), Some(0));
+
+        let mut attempts = 0;
+        db.transaction_with_retry(3, |txn| {
+            attempts += 1;
+            let balance = txn.get_for_update_typed(&balances, &key)?.unwrap();
+            if attempts == 1 {
+                balances.insert(&key, &50)?;
+            }
+            txn.put_typed(&balances, &key, &(balance + 10))?;
+
+            // NOTE: Reads include the staged writes of the transaction.
+            assert_eq!(txn.get_typed(&balances, &key)?, Some(balance + 10));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(attempts, 2);
+        assert_eq!(balances.get(&key).unwrap(), Some(60));
+    }
+
+    #[test]
+    fn test_transaction_write_conflict() {
+        let _ = fs::remove_dir_all("test_transaction_write_conflict.db");
+        let db = StructDB::builder("test_transaction_write_conflict.db", Caches::default())
+            .with_struct::<Balances>()
+            .build()
+            .unwrap();
+
+        let balances = db.make_typed_table::<Balances>();
+        let key = "carol".to_string();
+        balances.insert(&key, &100).unwrap();
+
+        // NOTE: Value written back to the one read is still a concurrent write.
+        let result = db.transaction(|txn| {
+            let balance = txn.get_for_update_typed(&balances, &key)?.unwrap();
+            balances.insert(&key, &0)?;
+            balances.insert(&key, &100)?;
+            txn.put_typed(&balances, &key, &(balance + 10))
+        });
+        assert!(matches!(result, Err(Error::TransactionConflict(_))));
+        assert_eq!(balances.get(&key).unwrap(), Some(100));
+
+        // NOTE: Blind writes conflict with writes since the snapshot, the first writer wins.
+        let result = db.transaction(|txn| {
+            txn.put_typed(&balances, &key, &1)?;
+            db.transaction(|other| other.put_typed(&balances, &key, &2))
+        });
+        assert!(matches!(result, Err(Error::TransactionConflict(_))));
+        assert_eq!(balances.get(&key).unwrap(), Some(2));
+
+        // NOTE: Writes of other keys do not conflict.
+        db.transaction(|txn| {
+            txn.put_typed(&balances, &key, &3)?;
+            balances.insert(&"dave".to_string(), &4)
+        })
+        .unwrap();
+        assert_eq!(balances.get(&key).unwrap(), Some(3));
+    }
+
+    #[test]
+    fn test_pessimistic_transaction_lock() {
+        let _ = fs::remove_dir_all("test_pessimistic_transaction_lock.db");
+        let db = StructDB::builder("test_pessimistic_transaction_lock.db", Caches::default())
+            .with_struct::<Balances>()
+            .with_transaction_mode(TransactionMode::Pessimistic)
+            .build()
+            .unwrap();
+
+        // NOTE: Sharded handles take part like any other table handle.
+        let balances = db.make_sharded_typed_table::<Balances>(&"eu".to_string());
+        let key = "bob".to_string();
+
+        let mut txn = db.begin_transaction();
+        assert_eq!(txn.mode(), TransactionMode::Pessimistic);
+        assert!(txn.get_for_update_typed(&balances, &key).unwrap().is_none());
+        txn.put_typed(&balances, &key, &1).unwrap();
+
+        // NOTE: Key is locked by the first transaction until it commits.
+        let result = db.transaction(|other| other.put_typed(&balances, &key, &2));
+        assert!(matches!(result, Err(Error::TransactionConflict(_))));
+
+        txn.commit().unwrap();
+        assert_eq!(balances.get(&key).unwrap(), Some(1));
+        db.transaction(|other| other.put_typed(&balances, &key, &2))
+            .unwrap();
+        assert_eq!(balances.get(&key).unwrap(), Some(2));
+
+        // NOTE: Table handles do not wait for locks of transactions, on the same thread either.
+        let mut txn = db.begin_transaction();
+        assert_eq!(txn.get_for_update_typed(&balances, &key).unwrap(), Some(2));
+        balances.insert(&key, &3).unwrap();
+        txn.put_typed(&balances, &key, &4).unwrap();
+        assert!(matches!(txn.commit(), Err(Error::TransactionConflict(_))));
+        assert_eq!(balances.get(&key).unwrap(), Some(3));
+    }
+}
@@ -0,0 +1,490 @@
+use std::marker::PhantomData;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    batch::Batch,
+    errors::Result,
+    index::{Index, IndexIterator, IndexWriter, Indexes},
+    key_encoding::{prefix_end, OrderedKey},
+    serialization::{deserialize, serialize},
+    table::{Table, TableImpl},
+};
+
+/// Table with typed keys and values.
+///
+/// Keys and values are encoded with bincode unless the codec functions are overridden.
+/// Tables scanned by key ranges should encode keys with `key_encoding::encode`,
+/// bincode integers are little-endian and do not sort numerically.
+pub trait TypedTable: Table {
+    type Key: Serialize + DeserializeOwned;
+    type Value: Serialize + DeserializeOwned;
+
+    /// Registers the secondary indexes which every typed handle of the table maintains.
+    ///
+    /// Writes through the raw `TableImpl` or `Transaction::put` bypass them,
+    /// `rebuild_index` brings an index back in sync.
+    fn indexes(indexes: &mut Indexes<Self>)
+    where
+        Self: Sized,
+    {
+        let _unused = indexes;
+    }
+
+    fn encode_key(key: &Self::Key) -> Result<Vec<u8>> {
+        serialize(key)
+    }
+
+    fn decode_key(encoded: &[u8]) -> Result<Self::Key> {
+        deserialize(encoded)
+    }
+
+    fn encode_value(value: &Self::Value) -> Result<Vec<u8>> {
+        serialize(value)
+    }
+
+    fn decode_value(encoded: &[u8]) -> Result<Self::Value> {
+        deserialize(encoded)
+    }
+}
+
+/// Number of index entries written at once by `rebuild_index`.
+const REBUILD_CHUNK: usize = 1000;
+
+pub struct TypedTableImpl<T>
+where
+    T: TypedTable,
+{
+    pub table: TableImpl<T>,
+    indexes: Vec<Box<dyn IndexWriter<T>>>,
+}
+
+impl<T> TypedTableImpl<T>
+where
+    T: TypedTable,
+{
+    pub fn new(table: TableImpl<T>) -> Self {
+        let mut indexes = Indexes::default();
+        T::indexes(&mut indexes);
+        let indexes = indexes.open(&table);
+
+        Self { table, indexes }
+    }
+
+    pub fn get(&self, key: &T::Key) -> Result<Option<T::Value>> {
+        let key = T::encode_key(key)?;
+        match self.table.get(key)? {
+            Some(value) => T::decode_value(value.as_ref()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads all keys with one batched lookup, values are returned in the order of `keys`.
+    pub fn multi_get<'k, I>(&self, keys: I) -> Result<Vec<Option<T::Value>>>
+    where
+        T::Key: 'k,
+        I: IntoIterator<Item = &'k T::Key>,
+    {
+        let keys = keys
+            .into_iter()
+            .map(T::encode_key)
+            .collect::<Result<Vec<_>>>()?;
+
+        self.table
+            .multi_get(&keys)?
+            .into_iter()
+            .map(|value| match value {
+                Some(value) => T::decode_value(value.as_ref()).map(Some),
+                None => Ok(None),
+            })
+            .collect()
+    }
+
+    pub fn insert(&self, key: &T::Key, value: &T::Value) -> Result<()> {
+        if self.indexes.is_empty() {
+            let key = T::encode_key(key)?;
+            let value = T::encode_value(value)?;
+            return self.table.insert(key, value).map_err(Into::into);
+        }
+
+        // NOTE: Conflicts mean the row changed after it was read, its index entries are staged again.
+        loop {
+            let mut batch = Batch::default();
+            self.stage_insert(&mut batch, key, value)?;
+            match self.table.apply(batch) {
+                Err(err) if err.is_conflict() => continue,
+                result => return result,
+            }
+        }
+    }
+
+    pub fn remove(&self, key: &T::Key) -> Result<()> {
+        if self.indexes.is_empty() {
+            let key = T::encode_key(key)?;
+            return self.table.remove(key).map_err(Into::into);
+        }
+
+        loop {
+            let mut batch = Batch::default();
+            self.stage_remove(&mut batch, key)?;
+            match self.table.apply(batch) {
+                Err(err) if err.is_conflict() => continue,
+                result => return result,
+            }
+        }
+    }
+
+    /// Adds the row and the changes of its index entries to the batch.
+    pub(crate) fn stage_insert(
+        &self,
+        batch: &mut Batch,
+        key: &T::Key,
+        value: &T::Value,
+    ) -> Result<()> {
+        let encoded = T::encode_key(key)?;
+        if !self.indexes.is_empty() {
+            let old = self.staged_or_stored(batch, &encoded)?;
+            for index in &self.indexes {
+                index.stage(batch, &encoded, old.as_ref(), Some(value))?;
+            }
+        }
+
+        batch.put(&self.table, encoded, T::encode_value(value)?);
+        Ok(())
+    }
+
+    /// Adds the removal of the row and of its index entries to the batch.
+    pub(crate) fn stage_remove(&self, batch: &mut Batch, key: &T::Key) -> Result<()> {
+        let encoded = T::encode_key(key)?;
+        if !self.indexes.is_empty() {
+            let old = self.staged_or_stored(batch, &encoded)?;
+            for index in &self.indexes {
+                index.stage(batch, &encoded, old.as_ref(), None)?;
+            }
+        }
+
+        batch.delete(&self.table, encoded);
+        Ok(())
+    }
+
+    /// Current value of the row, a write staged earlier in the batch takes precedence.
+    ///
+    /// A stored value is required to stay unchanged until the batch is written.
+    fn staged_or_stored(&self, batch: &mut Batch, encoded: &[u8]) -> Result<Option<T::Value>> {
+        match batch.staged(&self.table, encoded) {
+            Some(Some(value)) => T::decode_value(value).map(Some),
+            Some(None) => Ok(None),
+            None => {
+                let stored = self.table.get(encoded)?.map(|value| value.to_vec());
+                batch.require_stored(&self.table, encoded, stored.as_deref());
+                stored.map(|value| T::decode_value(&value)).transpose()
+            }
+        }
+    }
+
+    /// Returns all rows whose index key equals `key`.
+    pub fn lookup_by_index<I>(&self, key: &I::IndexKey) -> Result<Vec<(T::Key, T::Value)>>
+    where
+        I: Index<Source = T>,
+    {
+        let prefix = key.to_ordered_bytes();
+        let inner = self.index_iter::<I>(Some(&prefix), prefix_end(&prefix).as_deref());
+
+        IndexIterator::<T, I>::new(self, inner).collect()
+    }
+
+    /// Iterates over rows with index keys in `[from, to)` in the order of the index.
+    pub fn index_range<I>(
+        &'_ self,
+        from: Option<&I::IndexKey>,
+        to: Option<&I::IndexKey>,
+    ) -> IndexIterator<'_, T, I>
+    where
+        I: Index<Source = T>,
+    {
+        let from = from.map(|key| key.to_ordered_bytes());
+        let to = to.map(|key| key.to_ordered_bytes());
+        let inner = self.index_iter::<I>(from.as_deref(), to.as_deref());
+
+        IndexIterator::new(self, inner)
+    }
+
+    /// Drops all entries of the index and indexes every row of the table again.
+    ///
+    /// Entries are written in batches of `REBUILD_CHUNK` rows, lookups see only
+    /// part of the index until the rebuild returns. Returns the number of indexed
+    /// rows, fails on the first row whose key of a unique index is taken, leaving
+    /// the index incomplete. Rows written at the same time may leave stale entries.
+    pub fn rebuild_index<I>(&self) -> Result<usize>
+    where
+        I: Index<Source = T> + Send + Sync,
+    {
+        let index = self.index_table::<I>();
+        index.truncate()?;
+
+        let mut count = 0;
+        let mut batch = Batch::default();
+        for item in self.table.iter_start() {
+            let (key, value) = item?;
+            let value = T::decode_value(&value)?;
+            if I::index_key(&value).is_some() {
+                index.stage(&mut batch, &key, None, Some(&value))?;
+                count += 1;
+            }
+
+            if batch.len() >= REBUILD_CHUNK {
+                index.apply(std::mem::take(&mut batch))?;
+            }
+        }
+        index.apply(batch)?;
+
+        Ok(count)
+    }
+
+    fn index_table<I: Index<Source = T>>(&self) -> TableImpl<I> {
+        self.table.sibling::<I>()
+    }
+
+    /// Iterates over index entries in `[from, to)`, borrowing the database of this table.
+    fn index_iter<I: Index<Source = T>>(
+        &'_ self,
+        from: Option<&[u8]>,
+        to: Option<&[u8]>,
+    ) -> rocksdb::DBIterator<'_> {
+        let index = self.index_table::<I>();
+        let mut read_config = index.new_read_config();
+        if let Some(from) = from {
+            read_config.set_iterate_lower_bound(from.to_vec());
+        }
+        if let Some(to) = to {
+            read_config.set_iterate_upper_bound(to.to_vec());
+        }
+
+        self.table
+            .db()
+            .iterator_cf_opt(&index.cf(), read_config, rocksdb::IteratorMode::Start)
+    }
+
+    pub fn contains_key(&self, key: &T::Key) -> Result<bool> {
+        let key = T::encode_key(key)?;
+        self.table.contains_key(key).map_err(Into::into)
+    }
+
+    pub fn iter_start(&'_ self) -> TypedIterator<'_, T> {
+        TypedIterator::new(self.table.iter_start())
+    }
+
+    pub fn iter_end(&'_ self) -> TypedIterator<'_, T> {
+        TypedIterator::new(self.table.iter_end())
+    }
+
+    pub fn iter_from(&'_ self, key: &T::Key) -> Result<TypedIterator<'_, T>> {
+        let key = T::encode_key(key)?;
+        Ok(TypedIterator::new(self.table.iter_from(&key)))
+    }
+
+    /// Iterates over keys in `[from, to)` in the byte order of the encoded keys.
+    pub fn range(
+        &'_ self,
+        from: Option<&T::Key>,
+        to: Option<&T::Key>,
+    ) -> Result<TypedIterator<'_, T>> {
+        let from = from.map(T::encode_key).transpose()?;
+        let to = to.map(T::encode_key).transpose()?;
+
+        Ok(TypedIterator::new(
+            self.table.iter_range(from.as_deref(), to.as_deref()),
+        ))
+    }
+}
+
+/// Iterator which decodes entries of a typed table.
+pub struct TypedIterator<'a, T> {
+    inner: rocksdb::DBIterator<'a>,
+    _ty: PhantomData<T>,
+}
+
+impl<'a, T> TypedIterator<'a, T>
+where
+    T: TypedTable,
+{
+    pub fn new(inner: rocksdb::DBIterator<'a>) -> Self {
+        Self {
+            inner,
+            _ty: Default::default(),
+        }
+    }
+}
+
+impl<'a, T> Iterator for TypedIterator<'a, T>
+where
+    T: TypedTable,
+{
+    type Item = Result<(T::Key, T::Value)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.inner.next()?;
+
+        Some(
+            item.map_err(Into::into)
+                .and_then(|(key, value)| Ok((T::decode_key(&key)?, T::decode_value(&value)?))),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::{builder::StructDB, caches::Caches, errors::Error, key_encoding, table::Table};
+
+    use super::TypedTable;
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    struct Account {
+        owner: String,
+        balance: i64,
+    }
+
+    struct Accounts;
+
+    impl Table for Accounts {
+        const NAME: &'static str = "accounts";
+    }
+
+    impl TypedTable for Accounts {
+        type Key = String;
+        type Value = Account;
+    }
+
+    #[test]
+    fn test_typed_table() {
+        let _ = fs::remove_dir_all("test_typed_table.db");
+        let db = StructDB::builder("test_typed_table.db", Caches::default())
+            .with_struct::<Accounts>()
+            .build()
+            .unwrap();
+
+        let table = db.make_typed_table::<Accounts>();
+        let account = Account {
+            owner: "alice".to_string(),
+            balance: 100,
+        };
+
+        let key = "acc-1".to_string();
+        assert!(!table.contains_key(&key).unwrap());
+        assert!(table.get(&key).unwrap().is_none());
+
+        table.insert(&key, &account).unwrap();
+        assert!(table.contains_key(&key).unwrap());
+        assert_eq!(table.get(&key).unwrap(), Some(account.clone()));
+
+        let entries: Vec<(String, Account)> = table.iter_start().collect::<Result<_, _>>().unwrap();
+        assert_eq!(entries, vec![(key.clone(), account)]);
+
+        table.remove(&key).unwrap();
+        assert!(table.get(&key).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_typed_table_multi_get() {
+        let _ = fs::remove_dir_all("test_typed_table_multi_get.db");
+        let db = StructDB::builder("test_typed_table_multi_get.db", Caches::default())
+            .with_struct::<Accounts>()
+            .build()
+            .unwrap();
+
+        let table = db.make_typed_table::<Accounts>();
+        let keys: Vec<String> = (0..100).map(|i| format!("acc-{}", i)).collect();
+        for (i, key) in keys.iter().enumerate().filter(|(i, _)| i % 3 == 0) {
+            let account = Account {
+                owner: key.clone(),
+                balance: i as i64,
+            };
+            table.insert(key, &account).unwrap();
+        }
+
+        // NOTE: Results follow the order of the requested keys, not the key order.
+        let values = table.multi_get(keys.iter().rev()).unwrap();
+        let balances: Vec<Option<i64>> = values
+            .iter()
+            .map(|v| v.as_ref().map(|a| a.balance))
+            .collect();
+        let expected: Vec<Option<i64>> =
+            (0..100).rev().map(|i| (i % 3 == 0).then_some(i)).collect();
+        assert_eq!(balances, expected);
+
+        let raw = table
+            .table
+            .multi_get([Accounts::encode_key(&keys[0]).unwrap(), b"missing".to_vec()])
+            .unwrap();
+        assert!(raw[0].is_some() && raw[1].is_none());
+    }
+
+    #[test]
+    fn test_typed_table_decode_error() {
+        let _ = fs::remove_dir_all("test_typed_table_decode_error.db");
+        let db = StructDB::builder("test_typed_table_decode_error.db", Caches::default())
+            .with_struct::<Accounts>()
+            .build()
+            .unwrap();
+
+        let table = db.make_typed_table::<Accounts>();
+        let key = "acc-1".to_string();
+        let encoded = Accounts::encode_key(&key).unwrap();
+        table.table.insert(encoded, [1u8, 2]).unwrap();
+
+        let result = table.get(&key);
+        assert!(matches!(result, Err(Error::DeserializationFailed(_))));
+    }
+
+    struct Series;
+
+    impl Table for Series {
+        const NAME: &'static str = "series";
+    }
+
+    impl TypedTable for Series {
+        type Key = (String, i64);
+        type Value = u32;
+
+        fn encode_key(key: &Self::Key) -> crate::errors::Result<Vec<u8>> {
+            key_encoding::encode(key)
+        }
+
+        fn decode_key(encoded: &[u8]) -> crate::errors::Result<Self::Key> {
+            key_encoding::decode(encoded)
+        }
+    }
+
+    #[test]
+    fn test_typed_table_ordered_range() {
+        let _ = fs::remove_dir_all("test_typed_table_ordered_range.db");
+        let db = StructDB::builder("test_typed_table_ordered_range.db", Caches::default())
+            .with_struct::<Series>()
+            .build()
+            .unwrap();
+
+        let table = db.make_typed_table::<Series>();
+        for (i, ts) in [300i64, -5, 2, 256, 1, 70_000].iter().enumerate() {
+            table
+                .insert(&("cpu".to_string(), *ts), &(i as u32))
+                .unwrap();
+        }
+        table.insert(&("mem".to_string(), 0), &0).unwrap();
+
+        let keys: Vec<i64> = table.iter_start().map(|item| item.unwrap().0 .1).collect();
+        assert_eq!(keys, vec![-5, 1, 2, 256, 300, 70_000, 0]);
+
+        let from = ("cpu".to_string(), 2);
+        let to = ("cpu".to_string(), 301);
+        let keys: Vec<i64> = table
+            .range(Some(&from), Some(&to))
+            .unwrap()
+            .map(|item| item.unwrap().0 .1)
+            .collect();
+        assert_eq!(keys, vec![2, 256, 300]);
+    }
+}
@@ -0,0 +1,281 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::{self, Receiver, Sender},
+    RwLock,
+};
+
+use crate::{batch::StagedWrite, key_encoding::prefix_end};
+
+/// Write of a watched key, `value` is `None` if the key was removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchEvent {
+    pub key: Vec<u8>,
+    pub value: Option<Vec<u8>>,
+}
+
+enum Pattern {
+    Key(Vec<u8>),
+    Prefix(Vec<u8>),
+}
+
+impl Pattern {
+    fn matches(&self, key: &[u8]) -> bool {
+        match self {
+            Pattern::Key(watched) => watched.as_slice() == key,
+            Pattern::Prefix(prefix) => key.starts_with(prefix),
+        }
+    }
+
+    /// Keys `[start, end)` covered by the pattern, `None` stands for no upper bound.
+    fn range(&self) -> (Vec<u8>, Option<Vec<u8>>) {
+        match self {
+            Pattern::Key(key) => {
+                let mut end = key.clone();
+                end.push(0);
+                (key.clone(), Some(end))
+            }
+            Pattern::Prefix(prefix) => (prefix.clone(), prefix_end(prefix)),
+        }
+    }
+}
+
+/// Turns a stored value into the value reported to a subscription.
+pub(crate) type Decode = fn(&[u8]) -> Vec<u8>;
+
+struct Subscription {
+    cf_name: String,
+    pattern: Pattern,
+    decode: Option<Decode>,
+    sender: Sender<WatchEvent>,
+    closed: AtomicBool,
+}
+
+/// Subscriptions to key writes of a database, shared by its table handles.
+///
+/// Subscriptions end when their receiver is dropped, they are removed after
+/// the next matching write.
+#[derive(Default)]
+pub struct Watchers {
+    subscriptions: RwLock<Vec<Subscription>>,
+}
+
+impl Watchers {
+    pub(crate) fn watch_key(
+        &self,
+        cf_name: &str,
+        key: &[u8],
+        decode: Option<Decode>,
+    ) -> Receiver<WatchEvent> {
+        self.subscribe(cf_name, Pattern::Key(key.to_vec()), decode)
+    }
+
+    pub(crate) fn watch_prefix(
+        &self,
+        cf_name: &str,
+        prefix: &[u8],
+        decode: Option<Decode>,
+    ) -> Receiver<WatchEvent> {
+        self.subscribe(cf_name, Pattern::Prefix(prefix.to_vec()), decode)
+    }
+
+    fn subscribe(
+        &self,
+        cf_name: &str,
+        pattern: Pattern,
+        decode: Option<Decode>,
+    ) -> Receiver<WatchEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscriptions.write().unwrap().push(Subscription {
+            cf_name: cf_name.to_string(),
+            pattern,
+            decode,
+            sender,
+            closed: AtomicBool::new(false),
+        });
+        receiver
+    }
+
+    /// Whether any subscription watches keys of the column family.
+    pub(crate) fn is_watched(&self, cf_name: &str) -> bool {
+        self.subscriptions
+            .read()
+            .unwrap()
+            .iter()
+            .any(|subscription| subscription.cf_name == cf_name)
+    }
+
+    /// Parts of `[from, to)` covered by subscriptions to the column family.
+    ///
+    /// Ranges of overlapping subscriptions may overlap as well.
+    pub(crate) fn watched_ranges(
+        &self,
+        cf_name: &str,
+        from: &[u8],
+        to: &[u8],
+    ) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let subscriptions = self.subscriptions.read().unwrap();
+        subscriptions
+            .iter()
+            .filter(|subscription| subscription.cf_name == cf_name)
+            .filter_map(|subscription| {
+                let (start, end) = subscription.pattern.range();
+                let start = start.max(from.to_vec());
+                let end = match end {
+                    Some(end) if end.as_slice() < to => end,
+                    _ => to.to_vec(),
+                };
+                (start < end).then_some((start, end))
+            })
+            .collect()
+    }
+
+    /// Sends the write to all subscriptions matching the key.
+    ///
+    /// Writers only share the read lock, subscriptions whose receiver was
+    /// dropped are flagged and removed once the write lock is free.
+    pub(crate) fn notify(&self, cf_name: &str, key: &[u8], value: Option<&[u8]>) {
+        let mut closed = false;
+        {
+            let subscriptions = self.subscriptions.read().unwrap();
+            for subscription in subscriptions.iter() {
+                if subscription.closed.load(Ordering::Relaxed) {
+                    closed = true;
+                    continue;
+                }
+                if subscription.cf_name != cf_name || !subscription.pattern.matches(key) {
+                    continue;
+                }
+
+                let value = match (value, subscription.decode) {
+                    (Some(value), Some(decode)) => Some(decode(value)),
+                    (value, _) => value.map(|value| value.to_vec()),
+                };
+                let event = WatchEvent {
+                    key: key.to_vec(),
+                    value,
+                };
+                if subscription.sender.send(event).is_err() {
+                    subscription.closed.store(true, Ordering::Relaxed);
+                    closed = true;
+                }
+            }
+        }
+
+        if closed {
+            self.prune();
+        }
+    }
+
+    /// Sends the writes of an applied batch in the order they were staged.
+    pub(crate) fn notify_all(&self, writes: &[StagedWrite]) {
+        for write in writes {
+            self.notify(&write.cf_name, &write.key, write.value.as_deref());
+        }
+    }
+
+    /// Removes flagged subscriptions, left to a later write while other threads hold the lock.
+    fn prune(&self) {
+        if let Ok(mut subscriptions) = self.subscriptions.try_write() {
+            subscriptions.retain(|subscription| !subscription.closed.load(Ordering::Relaxed));
+        }
+    }
+
+    /// Number of subscriptions, including ones whose receiver was dropped but
+    /// which were not removed yet.
+    pub fn len(&self) -> usize {
+        self.subscriptions.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::mpsc::RecvTimeoutError, time::Duration};
+
+    use crate::{batch::Batch, builder::StructDB, caches::Caches, table::Table};
+
+    use super::WatchEvent;
+
+    struct Config;
+
+    impl Table for Config {
+        const NAME: &'static str = "config";
+    }
+
+    #[test]
+    fn test_watch() {
+        let _ = fs::remove_dir_all("test_watch.db");
+        let db = StructDB::builder("test_watch.db", Caches::default())
+            .with_struct::<Config>()
+            .build()
+            .unwrap();
+
+        let config = db.make_table::<Config>();
+        let key = config.watch("log-level");
+        let flags = config.watch_prefix("flag:");
+
+        // NOTE: Writes through another handle of the same database are delivered too.
+        let writer = db.make_table::<Config>();
+        writer.insert("log-level", "debug").unwrap();
+        writer.insert("flag:beta", "on").unwrap();
+        writer.insert("other", "x").unwrap();
+        assert!(writer
+            .compare_and_swap("flag:beta", Some(b"on"), Some(b"off"))
+            .unwrap());
+        writer.remove("log-level").unwrap();
+
+        let timeout = Duration::from_millis(100);
+        let events: Vec<WatchEvent> = key.try_iter().collect();
+        assert_eq!(
+            events,
+            vec![
+                WatchEvent {
+                    key: b"log-level".to_vec(),
+                    value: Some(b"debug".to_vec()),
+                },
+                WatchEvent {
+                    key: b"log-level".to_vec(),
+                    value: None,
+                },
+            ]
+        );
+
+        let values: Vec<Option<Vec<u8>>> = flags.try_iter().map(|event| event.value).collect();
+        assert_eq!(values, vec![Some(b"on".to_vec()), Some(b"off".to_vec())]);
+        assert_eq!(flags.recv_timeout(timeout), Err(RecvTimeoutError::Timeout));
+
+        // NOTE: Dropped receivers end their subscription without affecting others.
+        drop(key);
+        writer.insert("log-level", "info").unwrap();
+        writer.remove("flag:beta").unwrap();
+        assert_eq!(flags.recv_timeout(timeout).unwrap().value, None);
+
+        // NOTE: Batches, transactions and range deletions are reported too.
+        let mut batch = Batch::default();
+        batch.put(&writer, "flag:a", "1");
+        batch.delete(&writer, "flag:gone");
+        writer.apply(batch).unwrap();
+        let mut txn = db.begin_transaction();
+        txn.put(&writer, "flag:b", "2");
+        txn.commit().unwrap();
+        writer.delete_prefix("flag:").unwrap();
+
+        let events: Vec<(Vec<u8>, Option<Vec<u8>>)> = flags
+            .try_iter()
+            .map(|event| (event.key, event.value))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (b"flag:a".to_vec(), Some(b"1".to_vec())),
+                (b"flag:gone".to_vec(), None),
+                (b"flag:b".to_vec(), Some(b"2".to_vec())),
+                (b"flag:a".to_vec(), None),
+                (b"flag:b".to_vec(), None),
+            ]
+        );
+    }
+}
@@ -1,62 +1,271 @@
+use std::time::{Duration, Instant};
+
 use crate::errors::Result;
 use byte_counter::counter::ByteCounter;
-use rocksdb::{BoundColumnFamily, WriteBatch};
 
-use crate::database::Database;
-use std::{rc::Rc, sync::Arc};
+use crate::record::{Record, SeqRecord};
+use crate::topic::{stamped, Topic, TopicImpl, TOPIC_LAST_INSERT_KEY};
 
-pub struct WriteBuffer<'a> {
-    db: &'a Rc<Database>,
-    cf: Arc<BoundColumnFamily<'a>>,
-    pub last_insert: ByteCounter,
+/// Buffered producer which writes appended records to a topic in batches.
+///
+/// Keys are assigned when a record is added, the records are written once the
+/// buffer exceeds `txn_size` bytes, `max_records` records or `linger` time.
+/// Thresholds are checked by `add` and `poll`, an idle producer has to call
+/// `poll` to honour `linger`. Remaining records are written by `finish`, dropping
+/// the buffer flushes them as well but discards write errors.
+pub struct WriteBuffer<'a, T>
+where
+    T: Topic,
+{
+    topic: &'a mut TopicImpl<T>,
+    pub next_insert: ByteCounter,
 
-    buffer: Vec<Vec<u8>>,
+    buffer: Vec<SeqRecord>,
     buffer_size: u128,
+    buffer_since: Option<Instant>,
     txn_size: u128, // NOTE: specified in bytes
+    max_records: usize,
+    linger: Option<Duration>,
 }
 
-impl<'a> WriteBuffer<'a> {
-    pub const LAST_INSERT_KEY: &'a str = "last-insert";
+impl<'a, T> WriteBuffer<'a, T>
+where
+    T: Topic,
+{
+    pub const LAST_INSERT_KEY: &'a str = TOPIC_LAST_INSERT_KEY;
+
+    pub fn new(topic: &'a mut TopicImpl<T>) -> Self {
+        topic.reclaim();
+        let next_insert = topic.next_insert.clone();
 
-    pub fn new(db: &'a Rc<Database>, cf: Arc<BoundColumnFamily<'a>>) -> Self {
         Self {
-            db,
-            cf,
-            last_insert: ByteCounter::default(),
+            topic,
+            next_insert,
             buffer: vec![],
             buffer_size: 0,
+            buffer_since: None,
             txn_size: 64512,
+            max_records: usize::MAX,
+            linger: None,
         }
     }
 
-    pub fn add(&mut self, value: &[u8]) {
+    /// Flush once buffered values exceed given number of bytes.
+    pub fn with_txn_size(mut self, txn_size: u128) -> Self {
+        self.txn_size = txn_size;
+        self
+    }
+
+    /// Flush once given number of records is buffered.
+    pub fn with_max_records(mut self, max_records: usize) -> Self {
+        self.max_records = max_records;
+        self
+    }
+
+    /// Flush once the oldest buffered record waits longer than given duration.
+    pub fn with_linger(mut self, linger: Duration) -> Self {
+        self.linger = Some(linger);
+        self
+    }
+
+    /// Number of records waiting to be written.
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Buffers the value and returns the record with its assigned key.
+    ///
+    /// The record becomes durable once the buffer is flushed. If the flush fails
+    /// the value is not buffered and its key is assigned to the next value, so
+    /// `add` can be retried.
+    pub fn add(&mut self, value: &[u8]) -> Result<SeqRecord> {
+        let record = SeqRecord::new(stamped(&self.next_insert), Record::from(value));
+        let buffer_since = self.buffer_since;
+
+        self.next_insert = self.next_insert.next_id();
+        self.buffer_since = buffer_since.or_else(|| Some(Instant::now()));
         self.buffer_size += value.len() as u128;
-        self.buffer.push(value.to_owned());
+        self.buffer.push(record.clone());
+
+        if let Err(err) = self.flush(false) {
+            self.buffer.pop();
+            self.buffer_size -= value.len() as u128;
+            self.buffer_since = buffer_since;
+            self.next_insert = record.key;
+            return Err(err);
+        }
+        Ok(record)
     }
 
-    pub fn flush(&mut self, force: bool) -> Result<()> {
-        if !force && self.txn_size >= self.buffer_size {
-            return Ok(());
+    /// Flushes the buffer if a threshold is reached, including `linger` of an idle buffer.
+    ///
+    /// Returns the written records, see `time_to_flush` for when to poll next.
+    pub fn poll(&mut self) -> Result<Vec<SeqRecord>> {
+        self.flush(false)
+    }
+
+    /// Time until the buffered records exceed `linger`, `None` without linger or records.
+    pub fn time_to_flush(&self) -> Option<Duration> {
+        match (self.linger, self.buffer_since) {
+            (Some(linger), Some(since)) => Some(linger.saturating_sub(since.elapsed())),
+            _ => None,
+        }
+    }
+
+    /// Checks whether any of the flush thresholds is reached.
+    pub fn should_flush(&self) -> bool {
+        if self.buffer.is_empty() {
+            return false;
+        }
+
+        if self.buffer_size > self.txn_size || self.buffer.len() >= self.max_records {
+            return true;
         }
 
-        let mut batch = WriteBatch::default();
+        match (self.linger, self.buffer_since) {
+            (Some(linger), Some(since)) => since.elapsed() >= linger,
+            _ => false,
+        }
+    }
 
-        for record in self.buffer.iter() {
-            self.last_insert = self.last_insert.next_id();
-            batch.put_cf(&self.cf, self.last_insert.to_string(), record);
+    /// Writes buffered records in a single batch and returns them.
+    ///
+    /// Unless `force` is set, nothing is written before a flush threshold is reached.
+    pub fn flush(&mut self, force: bool) -> Result<Vec<SeqRecord>> {
+        if self.buffer.is_empty() || (!force && !self.should_flush()) {
+            return Ok(vec![]);
         }
 
-        // NOTE: Update `last-insert` value.
-        batch.put_cf(
-            &self.cf,
-            WriteBuffer::LAST_INSERT_KEY,
-            self.last_insert.to_string(),
-        );
-        self.db.set_batch(batch)?;
+        // NOTE: Records stay buffered with their keys if the write fails.
+        self.topic.write_records(&self.buffer)?;
 
-        self.buffer = vec![];
+        let flushed = std::mem::take(&mut self.buffer);
         self.buffer_size = 0;
+        self.buffer_since = None;
+
+        Ok(flushed)
+    }
+
+    /// Writes all remaining records and closes the buffer.
+    ///
+    /// On error the remaining records are discarded, their keys are not written.
+    pub fn finish(mut self) -> Result<Vec<SeqRecord>> {
+        let result = self.flush(true);
+        if result.is_err() {
+            self.buffer.clear();
+        }
+        result
+    }
+}
+
+impl<'a, T> Drop for WriteBuffer<'a, T>
+where
+    T: Topic,
+{
+    fn drop(&mut self) {
+        // NOTE: Fallback for producers which are not finished, errors can't be reported here.
+        let _ = self.flush(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, thread, time::Duration};
+
+    use crate::{builder::StructDB, caches::Caches, table::Table, topic::Topic};
+
+    struct MyTopic;
+
+    impl Table for MyTopic {
+        const NAME: &'static str = "my-topic";
+    }
+
+    impl Topic for MyTopic {}
+
+    #[test]
+    fn test_write_buffer_flush() {
+        let _ = fs::remove_dir_all("test_write_buffer_flush.db");
+        let db = StructDB::builder("test_write_buffer_flush.db", Caches::default())
+            .with_struct::<MyTopic>()
+            .build()
+            .unwrap();
+
+        let mut topic = db.make_topic::<MyTopic>();
+        let mut keys = vec![];
+
+        {
+            let mut producer = topic.producer().with_max_records(10);
+            for i in 0..25 {
+                let record = producer.add(&[i as u8; 32]).unwrap();
+                assert!(record.is_valid());
+                keys.push(record.key.to_string());
+
+                assert_eq!(producer.len(), (i + 1) % 10);
+            }
+            assert!(!producer.should_flush());
+
+            let flushed = producer.flush(true).unwrap();
+            assert_eq!(flushed.len(), 5);
+            assert!(producer.is_empty());
+
+            producer.add(&[0; 32]).unwrap();
+            assert_eq!(producer.finish().unwrap().len(), 1);
+        }
+
+        let received: Vec<String> = topic.iter().map(|r| r.key.to_string()).collect();
+        assert_eq!(received.len(), 26);
+        assert_eq!(&received[..25], &keys[..]);
+        assert_eq!(topic.last_insert().unwrap().to_string(), received[25]);
+    }
+
+    #[test]
+    fn test_write_buffer_txn_size() {
+        let _ = fs::remove_dir_all("test_write_buffer_txn_size.db");
+        let db = StructDB::builder("test_write_buffer_txn_size.db", Caches::default())
+            .with_struct::<MyTopic>()
+            .build()
+            .unwrap();
+
+        let mut topic = db.make_topic::<MyTopic>();
+        let mut producer = topic.producer().with_txn_size(100);
+
+        for _ in 0..3 {
+            producer.add(&[1; 32]).unwrap();
+        }
+        assert_eq!(producer.len(), 3);
+
+        producer.add(&[1; 32]).unwrap();
+        assert!(producer.is_empty());
+    }
+
+    #[test]
+    fn test_write_buffer_linger() {
+        let _ = fs::remove_dir_all("test_write_buffer_linger.db");
+        let db = StructDB::builder("test_write_buffer_linger.db", Caches::default())
+            .with_struct::<MyTopic>()
+            .build()
+            .unwrap();
+
+        let mut topic = db.make_topic::<MyTopic>();
+        let mut producer = topic.producer().with_linger(Duration::from_millis(50));
+        assert_eq!(producer.time_to_flush(), None);
+
+        producer.add(&[1; 8]).unwrap();
+        assert!(producer.poll().unwrap().is_empty());
+        assert!(producer.time_to_flush().unwrap() <= Duration::from_millis(50));
+
+        // NOTE: Idle buffer is written by `poll` once linger elapsed, without another `add`.
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(producer.time_to_flush(), Some(Duration::ZERO));
+        assert_eq!(producer.poll().unwrap().len(), 1);
+        assert!(producer.is_empty());
+        assert_eq!(producer.time_to_flush(), None);
+        drop(producer);
 
-        Ok(())
+        assert_eq!(topic.iter().count(), 1);
     }
 }